@@ -23,7 +23,10 @@
         };
 
         devShells.default = pkgs.mkShell {
-          buildInputs = with pkgs; [ rust-bin.stable.latest.default ];
+          # wasm32 for checking that the library builds without the native feature
+          buildInputs = with pkgs; [
+            (rust-bin.stable.latest.default.override { targets = [ "wasm32-unknown-unknown" ]; })
+          ];
         };
 
         packages.pta-template-engine = pkgs.pta-template-engine;
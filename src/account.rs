@@ -0,0 +1,103 @@
+/// The character between the segments of an account, as in `Assets:Checking`, unless a
+/// journal uses another.
+pub const ACCOUNT_SEPARATOR: char = ':';
+
+/// How `normalize_account` rewrites the letters of each `:` separated segment of an account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+pub enum AccountCase {
+    /// Leave letters alone and only trim whitespace
+    Preserve,
+    /// Upper case the first letter of each segment and leave the rest, so `assets:checking`
+    /// becomes `Assets:Checking` while `IRA` and `eBay` are untouched
+    #[default]
+    Capitalize,
+    /// Lower case every letter
+    Lower,
+}
+
+/// Canonicalize an account name so that spellings hledger would treat as different accounts, such
+/// as `assets : checking` and `Assets:Checking`, come out the same.
+///
+/// Whitespace around each segment is trimmed and the segment's letters are rewritten according to
+/// `case`. Whitespace inside a segment, as in `Credit Card`, is kept. Empty segments, as in
+/// `Assets::Cash` or a trailing `:`, are kept too, so that `ptatemp lint` can still report them.
+pub fn normalize_account(account: &str, case: AccountCase) -> String {
+    normalize_account_with(account, case, ACCOUNT_SEPARATOR)
+}
+
+/// `normalize_account` for a journal whose segments are separated by `separator`, such as `/`.
+pub fn normalize_account_with(account: &str, case: AccountCase, separator: char) -> String {
+    account
+        .split(separator)
+        .map(|segment| {
+            let segment = segment.trim();
+            match case {
+                AccountCase::Preserve => segment.to_string(),
+                AccountCase::Lower => segment.to_lowercase(),
+                AccountCase::Capitalize => {
+                    let mut chars = segment.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                }
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(&separator.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_each_case() {
+        let account = "assets : IRA:eBay store";
+        assert_eq!(
+            normalize_account(account, AccountCase::Preserve),
+            "assets:IRA:eBay store"
+        );
+        assert_eq!(
+            normalize_account(account, AccountCase::Capitalize),
+            "Assets:IRA:EBay store"
+        );
+        assert_eq!(
+            normalize_account(account, AccountCase::Lower),
+            "assets:ira:ebay store"
+        );
+    }
+
+    #[test]
+    fn normalizes_with_a_custom_separator() {
+        assert_eq!(
+            normalize_account_with("assets / checking:main", AccountCase::Capitalize, '/'),
+            "Assets/Checking:main"
+        );
+    }
+
+    #[test]
+    fn keeps_repeated_and_trailing_separators() {
+        assert_eq!(
+            normalize_account("assets::cash", AccountCase::Capitalize),
+            "Assets::Cash"
+        );
+        assert_eq!(
+            normalize_account("assets:cash:", AccountCase::Capitalize),
+            "Assets:Cash:"
+        );
+    }
+
+    #[test]
+    fn trims_surrounding_whitespace() {
+        assert_eq!(
+            normalize_account("  expenses:food  ", AccountCase::Preserve),
+            "expenses:food"
+        );
+        assert_eq!(
+            normalize_account("\tExpenses: Credit Card \n", AccountCase::Lower),
+            "expenses:credit card"
+        );
+    }
+}
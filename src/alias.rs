@@ -0,0 +1,142 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::account::ACCOUNT_SEPARATOR;
+
+/// Short account codes that expand to full account paths, e.g. `chk` to `Assets:Bank:Checking`.
+///
+/// An alias matches an account exactly or as its leading segments, so with the alias above
+/// `chk:Joint` resolves to `Assets:Bank:Checking:Joint`. When several aliases match, the longest
+/// one wins.
+#[derive(Debug, Clone)]
+pub struct AccountAliases {
+    aliases: HashMap<String, String>,
+    /// What follows an alias used as the leading segments of a longer account
+    separator: char,
+}
+
+impl Default for AccountAliases {
+    fn default() -> Self {
+        Self {
+            aliases: HashMap::new(),
+            separator: ACCOUNT_SEPARATOR,
+        }
+    }
+}
+
+impl AccountAliases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match aliases against accounts whose segments are separated by `separator` rather than
+    /// `:`.
+    pub fn separator(self, separator: char) -> Self {
+        Self { separator, ..self }
+    }
+
+    pub fn insert<C, F>(&mut self, code: C, full: F)
+    where
+        C: ToString,
+        F: ToString,
+    {
+        self.aliases.insert(code.to_string(), full.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    pub fn resolve<'a>(&self, account: &'a str) -> Cow<'a, str> {
+        let best = self
+            .aliases
+            .iter()
+            .filter(|(code, _)| match account.strip_prefix(code.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with(self.separator),
+                None => false,
+            })
+            .max_by_key(|(code, _)| code.len());
+        match best {
+            Some((code, full)) => Cow::Owned(format!("{}{}", full, &account[code.len()..])),
+            None => Cow::Borrowed(account),
+        }
+    }
+}
+
+impl Extend<(String, String)> for AccountAliases {
+    fn extend<T: IntoIterator<Item = (String, String)>>(&mut self, iter: T) {
+        self.aliases.extend(iter);
+    }
+}
+
+/// Parse a `code=Full:Account` alias definition.
+pub fn parse_alias(value: &str) -> Option<(String, String)> {
+    let (code, full) = value.split_once('=')?;
+    let (code, full) = (code.trim(), full.trim());
+    if code.is_empty() || full.is_empty() {
+        return None;
+    }
+    Some((code.to_string(), full.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(separator: char, pairs: &[(&str, &str)]) -> AccountAliases {
+        let mut aliases = AccountAliases::new().separator(separator);
+        for (code, full) in pairs {
+            aliases.insert(code, full);
+        }
+        aliases
+    }
+
+    #[test]
+    fn an_alias_only_matches_whole_segments() {
+        let aliases = aliases(':', &[("Assets:Bank", "Assets:Checking")]);
+        assert_eq!(aliases.resolve("Assets:Bank"), "Assets:Checking");
+        assert_eq!(
+            aliases.resolve("Assets:Bank:Joint"),
+            "Assets:Checking:Joint"
+        );
+        assert!(matches!(
+            aliases.resolve("Assets:BankOld"),
+            Cow::Borrowed("Assets:BankOld")
+        ));
+        assert_eq!(
+            aliases.resolve("Savings:Assets:Bank"),
+            "Savings:Assets:Bank"
+        );
+    }
+
+    #[test]
+    fn the_longest_matching_alias_wins() {
+        let aliases = aliases(
+            ':',
+            &[
+                ("chk", "Assets:Checking"),
+                ("chk:j", "Assets:Joint Checking"),
+            ],
+        );
+        assert_eq!(aliases.resolve("chk:j"), "Assets:Joint Checking");
+        assert_eq!(aliases.resolve("chk:j:Fees"), "Assets:Joint Checking:Fees");
+        assert_eq!(aliases.resolve("chk:Fees"), "Assets:Checking:Fees");
+    }
+
+    #[test]
+    fn a_custom_separator_bounds_the_match() {
+        let aliases = aliases('/', &[("chk", "Assets/Checking")]);
+        assert_eq!(aliases.resolve("chk/Joint"), "Assets/Checking/Joint");
+        assert_eq!(aliases.resolve("chk:Joint"), "chk:Joint");
+    }
+
+    #[test]
+    fn parse_alias_trims_and_rejects_empty_sides() {
+        assert_eq!(
+            parse_alias(" chk = Assets:Checking "),
+            Some((String::from("chk"), String::from("Assets:Checking")))
+        );
+        assert_eq!(parse_alias("chk="), None);
+        assert_eq!(parse_alias("=Assets:Checking"), None);
+        assert_eq!(parse_alias("chk"), None);
+    }
+}
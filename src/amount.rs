@@ -0,0 +1,370 @@
+use std::fmt::Display;
+
+use crate::transaction::LineItemBuilderError;
+
+/// The commodity assumed for amounts written without one.
+pub const DEFAULT_COMMODITY: &str = "$";
+
+/// A quantity, in hundredths, of some commodity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Amount {
+    pub value: i64,
+    pub commodity: String,
+}
+
+impl Amount {
+    pub fn new<S>(value: i64, commodity: S) -> Self
+    where
+        S: ToString,
+    {
+        Self {
+            value,
+            commodity: commodity.to_string(),
+        }
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format_amount(self.value, &self.commodity))
+    }
+}
+
+/// Symbols such as `$` or `€` are written before the number, codes such as `EUR` or `AAPL` after
+/// it, separated by a space.
+pub fn commodity_is_prefix(commodity: &str) -> bool {
+    !commodity.chars().any(char::is_alphabetic)
+}
+
+pub fn format_amount(value: i64, commodity: &str) -> String {
+    let number = format!("{:.02}", value as f64 / 100.0);
+    if commodity_is_prefix(commodity) {
+        format!("{}{}", commodity, number)
+    } else {
+        format!("{} {}", number, commodity)
+    }
+}
+
+/// The character that separates whole units from the fractional part of an amount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecimalMark {
+    /// `1,234.56`
+    #[default]
+    Period,
+    /// `1.234,56`
+    Comma,
+}
+
+impl DecimalMark {
+    pub fn decimal(self) -> char {
+        match self {
+            Self::Period => '.',
+            Self::Comma => ',',
+        }
+    }
+
+    pub fn group(self) -> char {
+        match self {
+            Self::Period => ',',
+            Self::Comma => '.',
+        }
+    }
+}
+
+impl TryFrom<char> for DecimalMark {
+    type Error = char;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            '.' => Ok(Self::Period),
+            ',' => Ok(Self::Comma),
+            c => Err(c),
+        }
+    }
+}
+
+/// What the number written in a posting counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+pub enum AmountUnit {
+    /// Whole units, so `12.50` is twelve dollars and fifty cents
+    #[default]
+    Dollars,
+    /// Hundredths, so `1250` is twelve dollars and fifty cents; fractions are rejected
+    Cents,
+}
+
+/// Settings that control how the amount of a posting is read.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub decimal_mark: DecimalMark,
+    pub unit: AmountUnit,
+    /// Reject amounts with more fractional digits than this rather than rounding them away.
+    /// Defaults to 2.
+    pub max_decimal_places: usize,
+    /// What an amount written as a percentage, such as `18%`, is a percentage of
+    pub percent_base: Option<Amount>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            decimal_mark: DecimalMark::default(),
+            unit: AmountUnit::default(),
+            max_decimal_places: 2,
+            percent_base: None,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decimal_mark(self, decimal_mark: DecimalMark) -> Self {
+        Self {
+            decimal_mark,
+            ..self
+        }
+    }
+
+    pub fn unit(self, unit: AmountUnit) -> Self {
+        Self { unit, ..self }
+    }
+
+    pub fn max_decimal_places(self, max_decimal_places: usize) -> Self {
+        Self {
+            max_decimal_places,
+            ..self
+        }
+    }
+
+    pub fn percent_base(self, percent_base: Amount) -> Self {
+        Self {
+            percent_base: Some(percent_base),
+            ..self
+        }
+    }
+}
+
+/// `percent`, written like `18%` or `-2.5%`, of `options.percent_base`, rounded to the nearest
+/// hundredth in the base's commodity.
+fn parse_percent(percent: &str, options: &ParseOptions) -> Result<Amount, LineItemBuilderError> {
+    let base = options
+        .percent_base
+        .as_ref()
+        .ok_or(LineItemBuilderError::MissingPercentBase)?;
+    let ratio: f64 = normalize_number(percent.trim(), options.decimal_mark)?
+        .parse()
+        .or(Err(LineItemBuilderError::ParseValue(format!(
+            "{}%",
+            percent
+        ))))?;
+    let value = (base.value as f64 * ratio / 100.0).round() as i64;
+    Ok(Amount::new(value, &base.commodity))
+}
+
+/// Rewrite a number that may contain digit group separators into the plain `1234.56` form that
+/// `str::parse` understands.
+///
+/// At most one decimal mark is allowed, and it must come after every group separator. When group
+/// separators are present, the first group must have one to three digits and every following
+/// group exactly three, so `1,23,456` is rejected rather than guessed at. Under
+/// `DecimalMark::Comma` a lone comma is therefore always read as the decimal mark: `1,234` is
+/// one and some fraction, not one thousand.
+pub fn normalize_number(raw: &str, mark: DecimalMark) -> Result<String, LineItemBuilderError> {
+    let invalid = || LineItemBuilderError::ParseValue(raw.to_string());
+    let (sign, unsigned) = match raw.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", raw.strip_prefix('+').unwrap_or(raw)),
+    };
+    let (integer, fraction) = match unsigned.split_once(mark.decimal()) {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (unsigned, None),
+    };
+
+    let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if let Some(fraction) = fraction {
+        if !is_digits(fraction) {
+            return Err(invalid());
+        }
+    }
+    let mut groups = integer.split(mark.group());
+    let first = groups.next().unwrap_or_default();
+    let first_is_valid = if integer.contains(mark.group()) {
+        is_digits(first) && first.len() <= 3
+    } else {
+        is_digits(first) || (first.is_empty() && fraction.is_some())
+    };
+    if !first_is_valid {
+        return Err(invalid());
+    }
+    let mut digits = first.to_string();
+    for group in groups {
+        if !(is_digits(group) && group.len() == 3) {
+            return Err(invalid());
+        }
+        digits.push_str(group);
+    }
+
+    match fraction {
+        Some(fraction) => Ok(format!("{}{}.{}", sign, digits, fraction)),
+        None => Ok(format!("{}{}", sign, digits)),
+    }
+}
+
+/// Parse a posting amount such as `$1,234.56`, `-$5`, or `10 AAPL` into hundredths of its
+/// commodity, reading the number in `options.unit`. The commodity may come before or after the
+/// number; without one it is `DEFAULT_COMMODITY`. A number wrapped in parentheses, as in
+/// `($12.00)` or `$(12.00)`, is negative. A number with more than `options.max_decimal_places`
+/// fractional digits, such as `$1.234`, is an error. A percentage such as `18%` is that share of
+/// `options.percent_base`, and an error without one.
+pub fn parse_amount(raw: &str, options: &ParseOptions) -> Result<Amount, LineItemBuilderError> {
+    let invalid = || LineItemBuilderError::ParseValue(raw.to_string());
+    let is_number = |c: char| c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '+');
+    let raw_trimmed = raw.trim();
+    if let Some(percent) = raw_trimmed.strip_suffix('%') {
+        return parse_percent(percent, options);
+    }
+    // Accounting notation: `($12.00)` or `$(12.00)` is negative
+    let parenthesized = match (raw_trimmed.find('('), raw_trimmed.rfind(')')) {
+        (None, None) => None,
+        (Some(open), Some(close))
+            if open < close && raw_trimmed.matches(['(', ')']).count() == 2 =>
+        {
+            Some(format!(
+                "{}{}{}",
+                &raw_trimmed[..open],
+                &raw_trimmed[open + 1..close],
+                &raw_trimmed[close + 1..]
+            ))
+        }
+        _ => return Err(invalid()),
+    };
+    let is_negated = parenthesized.is_some();
+    let trimmed = parenthesized.as_deref().unwrap_or(raw_trimmed).trim();
+    if is_negated && trimmed.contains(['-', '+']) {
+        return Err(invalid());
+    }
+    let (sign, unsigned) = match trimmed.strip_prefix('-') {
+        Some(rest) if !rest.starts_with(is_number) => ("-", rest),
+        _ => ("", trimmed),
+    };
+    let number_start = unsigned.find(is_number).ok_or_else(invalid)?;
+    let (prefix, rest) = unsigned.split_at(number_start);
+    let number_end = rest.find(|c| !is_number(c)).unwrap_or(rest.len());
+    let (number, suffix) = rest.split_at(number_end);
+    let commodity = match (prefix.trim(), suffix.trim()) {
+        ("", "") => DEFAULT_COMMODITY,
+        (c, "") | ("", c) => c,
+        _ => return Err(invalid()),
+    };
+
+    let number = format!(
+        "{}{}",
+        sign,
+        normalize_number(number, options.decimal_mark)?
+    );
+    let decimal_places = number
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len());
+    if decimal_places > options.max_decimal_places {
+        return Err(invalid());
+    }
+    let value = match options.unit {
+        AmountUnit::Dollars => {
+            let value: f64 = number.parse().or(Err(invalid()))?;
+            (value * 100.0).round() as i64
+        }
+        AmountUnit::Cents => number.parse().or(Err(invalid()))?,
+    };
+    let value = if is_negated { -value } else { value };
+    Ok(Amount::new(value, commodity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_number_accepts_valid_grouping() {
+        assert_eq!(
+            normalize_number("1,234.56", DecimalMark::Period).unwrap(),
+            "1234.56"
+        );
+        assert_eq!(
+            normalize_number("1.234,56", DecimalMark::Comma).unwrap(),
+            "1234.56"
+        );
+        assert_eq!(
+            normalize_number("-12,345,678", DecimalMark::Period).unwrap(),
+            "-12345678"
+        );
+    }
+
+    #[test]
+    fn normalize_number_reads_a_lone_comma_by_decimal_mark() {
+        assert_eq!(normalize_number("1,5", DecimalMark::Comma).unwrap(), "1.5");
+        assert_eq!(
+            normalize_number("1,234", DecimalMark::Comma).unwrap(),
+            "1.234"
+        );
+        assert!(normalize_number("1,5", DecimalMark::Period).is_err());
+    }
+
+    #[test]
+    fn normalize_number_rejects_the_other_convention() {
+        assert!(normalize_number("1.234,56", DecimalMark::Period).is_err());
+        assert!(normalize_number("1,234.56", DecimalMark::Comma).is_err());
+    }
+
+    #[test]
+    fn normalize_number_rejects_malformed_grouping() {
+        for raw in [
+            "1,23,456", "1234,567", ",123", "1,,234", "1,234,", "1,2345", "1.2.3",
+        ] {
+            assert!(
+                normalize_number(raw, DecimalMark::Period).is_err(),
+                "{} should be rejected",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn parse_amount_reads_parentheses_as_negative() {
+        let options = ParseOptions::new();
+        assert_eq!(
+            parse_amount("($12.00)", &options).unwrap(),
+            Amount::new(-1200, "$")
+        );
+        assert_eq!(
+            parse_amount("$(12.00)", &options).unwrap(),
+            Amount::new(-1200, "$")
+        );
+        assert_eq!(
+            parse_amount("(10 AAPL)", &options).unwrap(),
+            Amount::new(-1000, "AAPL")
+        );
+    }
+
+    #[test]
+    fn parse_amount_rejects_unbalanced_or_signed_parentheses() {
+        let options = ParseOptions::new();
+        for raw in [
+            "($12.00",
+            "$12.00)",
+            ")12(",
+            "((12))",
+            "(-$12.00)",
+            "$(+12)",
+        ] {
+            assert!(
+                parse_amount(raw, &options).is_err(),
+                "{} should be rejected",
+                raw
+            );
+        }
+    }
+}
@@ -0,0 +1,818 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::account::{normalize_account_with, AccountCase};
+use crate::alias::AccountAliases;
+use crate::amount::{format_amount, parse_amount, DecimalMark, ParseOptions};
+use crate::error::LedgerError;
+use crate::placeholder::substitute;
+use crate::transaction::Utf8Decoding;
+
+/// Wait before the first retry of a failed hledger/ledger run; each later retry waits twice as
+/// long as the one before.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often a run under `timeout` is checked for having finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Something worth telling the user while hledger/ledger run; see `BalanceOptions::on_event`.
+#[derive(Debug)]
+pub enum RunEvent<'a> {
+    /// A command about to run, written as it would be typed into a POSIX shell. Only reported
+    /// under `show_command`.
+    Started(&'a str),
+    /// A run failed and will be tried again after `delay`
+    Retrying {
+        program: &'a str,
+        failure: &'a str,
+        delay: Duration,
+    },
+}
+
+impl std::fmt::Display for RunEvent<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Started(command) => write!(f, "{}", command),
+            Self::Retrying {
+                program,
+                failure,
+                delay,
+            } => write!(
+                f,
+                "{} failed ({}); retrying in {}ms",
+                program,
+                failure,
+                delay.as_millis()
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BalanceOptions {
+    pub journal: PathBuf,
+    /// Report every hledger/ledger command run as a `RunEvent::Started`
+    pub show_command: bool,
+    /// Decimal mark hledger/ledger use in their output; group separators are dropped
+    pub decimal_mark: DecimalMark,
+    pub aliases: AccountAliases,
+    /// Applied to placeholder accounts after aliases are resolved
+    pub normalize_accounts: Option<AccountCase>,
+    /// Separates account segments for aliases, normalization, and linting
+    pub account_separator: char,
+    /// Balances to use instead of asking hledger/ledger
+    pub cache: Option<BalanceCache>,
+    /// Fail on a balance missing from `cache` rather than falling back to hledger/ledger
+    pub strict: bool,
+    /// Journal of market prices for placeholders converted with `in=`
+    pub price_db: Option<PathBuf>,
+    /// Have hledger infer market prices from transaction costs for `in=` conversions
+    pub infer_market_prices: bool,
+    /// Look placeholder accounts up in `cache` regardless of case. hledger/ledger account queries
+    /// ignore case already.
+    pub fold_case_accounts: bool,
+    /// Run hledger/ledger again this many times when it exits unsuccessfully or cannot be
+    /// started for a reason other than not being installed
+    pub retries: u32,
+    /// Kill hledger/ledger if a run takes longer than this
+    pub timeout: Option<Duration>,
+    /// Every plain account balance looked up so far, for `--dump-balances`
+    pub resolved: RefCell<BTreeMap<String, i64>>,
+    /// Called with each `RunEvent`; nothing is reported without it
+    pub on_event: Option<fn(&RunEvent)>,
+}
+
+impl BalanceOptions {
+    /// Resolve aliases in `account`, then normalize it if asked to. Postings and placeholders both
+    /// go through here so that they agree on account names.
+    pub fn canonical_account(&self, account: &str) -> String {
+        let account = self.aliases.resolve(account);
+        match self.normalize_accounts {
+            Some(case) => normalize_account_with(&account, case, self.account_separator),
+            None => account.into_owned(),
+        }
+    }
+
+    fn report(&self, event: &RunEvent) {
+        if let Some(on_event) = self.on_event {
+            on_event(event);
+        }
+    }
+
+    /// Run `program` against the journal, retrying up to `retries` times with a backoff, and
+    /// return the last attempt. A missing program and a run that timed out are not retried.
+    fn run(&self, program: &str, args: &[&str], valued: bool) -> std::io::Result<Output> {
+        let mut delay = RETRY_BACKOFF;
+        let mut retried = 0;
+        loop {
+            let result = self.run_once(program, args, valued);
+            let failure = match &result {
+                Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::TimedOut) => None,
+                Err(e) => Some(e.to_string()),
+                Ok(output) if !output.status.success() => Some(output.status.to_string()),
+                Ok(_) => None,
+            };
+            match failure {
+                Some(failure) if retried < self.retries => {
+                    self.report(&RunEvent::Retrying {
+                        program,
+                        failure: &failure,
+                        delay,
+                    });
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    retried += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    /// Run `program` against the journal once. A `valued` report converts commodities, so it is
+    /// also given the price database and asked to infer prices if those were requested.
+    fn run_once(&self, program: &str, args: &[&str], valued: bool) -> std::io::Result<Output> {
+        let mut command = Command::new(program);
+        command.arg("-f").arg(&self.journal);
+        if valued {
+            if let Some(price_db) = &self.price_db {
+                match program {
+                    "ledger" => command.arg("--price-db").arg(price_db),
+                    _ => command.arg("-f").arg(price_db),
+                };
+            }
+            if self.infer_market_prices && program != "ledger" {
+                command.arg("--infer-market-prices");
+            }
+        }
+        command.args(args);
+        if self.show_command {
+            self.report(&RunEvent::Started(&display_command(&command)));
+        }
+        match self.timeout {
+            Some(timeout) => output_within(command, timeout),
+            None => command.output(),
+        }
+    }
+}
+
+/// `command.output()`, except that the child is killed and an error of kind `TimedOut` returned
+/// once it has run for `timeout`.
+fn output_within(mut command: Command, timeout: Duration) -> std::io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Drained on their own threads so that a child filling a pipe cannot stall while it is waited
+    // for
+    let drain = |mut pipe: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut bytes = Vec::new();
+            pipe.read_to_end(&mut bytes).map(|_| bytes)
+        })
+    };
+    let stdout = drain(Box::new(child.stdout.take().expect("stdout is piped")));
+    let stderr = drain(Box::new(child.stderr.take().expect("stderr is piped")));
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // It may have exited since `try_wait`, so failing to kill it is not an error
+            let _ = child.kill();
+            child.wait()?;
+            return Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("still running after {}s", timeout.as_secs()),
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    let join = |reader: std::thread::JoinHandle<std::io::Result<Vec<u8>>>| {
+        reader.join().expect("pipe reader does not panic")
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    })
+}
+
+/// A snapshot of account balances, stored as `account<TAB>balance` lines such as
+/// `Assets:Checking\t-1234.56`. Blank lines and lines starting with `#` are skipped.
+///
+/// Only placeholders without `query=` or `period=` modifiers are looked up in the cache, since the
+/// snapshot holds a single all-time balance per account.
+#[derive(Debug, Default)]
+pub struct BalanceCache {
+    balances: HashMap<String, i64>,
+    /// `balances` keyed by lower-cased account, for lookups that ignore case. Where accounts
+    /// differ only in case, the first in the file wins.
+    folded: HashMap<String, i64>,
+}
+
+impl BalanceCache {
+    pub fn load(path: &Path) -> Result<Self, LedgerError> {
+        let mut balances = HashMap::new();
+        let mut folded = HashMap::new();
+        for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                LedgerError::Misc(format!(
+                    "Line {} of balance cache {} is not `account<TAB>balance`",
+                    i + 1,
+                    path.display()
+                ))
+            };
+            let (account, balance) = line.split_once('\t').ok_or_else(invalid)?;
+            let amount = parse_amount(balance, &ParseOptions::new()).map_err(|_| invalid())?;
+            let account = account.trim();
+            balances.insert(account.to_string(), amount.value);
+            folded.entry(account.to_lowercase()).or_insert(amount.value);
+        }
+        Ok(Self { balances, folded })
+    }
+
+    /// The cached balance of `account`, ignoring case if `fold_case` is set.
+    fn get(&self, account: &str, fold_case: bool) -> Option<i64> {
+        match self.balances.get(account) {
+            Some(balance) => Some(*balance),
+            None if fold_case => self.folded.get(&account.to_lowercase()).copied(),
+            None => None,
+        }
+    }
+
+    /// Write `balances` in the format `load` reads.
+    pub fn write(path: &Path, balances: &BTreeMap<String, i64>) -> Result<(), LedgerError> {
+        let contents: String = balances
+            .iter()
+            .map(|(account, value)| format!("{}\t{}\n", account, format_amount(*value, "")))
+            .collect();
+        Ok(std::fs::write(path, contents)?)
+    }
+}
+
+/// Render a command the way it would be typed into a POSIX shell so it can be copied and rerun.
+fn display_command(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn shell_quote(arg: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[derive(Debug)]
+struct Placeholder {
+    account: String,
+    /// `account` as given to hledger/ledger; see `account_pattern`
+    pattern: String,
+    query: Vec<String>,
+    period: Option<String>,
+    /// Round the balance to the nearest multiple of this many cents, e.g. `round=10` for tens
+    round: Option<i64>,
+    /// Substitute the number of matching postings, as a plain number, instead of the balance
+    count: bool,
+    /// Convert the balance to this commodity at market prices, e.g. `in=USD`
+    convert: Option<String>,
+}
+
+/// Push `c` onto `regex` so that it matches only itself.
+fn push_literal(regex: &mut String, c: char) {
+    if "\\.+*?()[]{}^$|".contains(c) {
+        regex.push('\\');
+    }
+    regex.push(c);
+}
+
+/// An anchored regex matching the account names that `glob` does, where `*` stands for any run
+/// of characters, `?` for any one character, and everything else for itself.
+fn glob_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => push_literal(&mut regex, c),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// The query hledger/ledger are given for `account`, which they read as a regex: `glob_regex`
+/// for a glob, otherwise the account with every regex character escaped, so that
+/// `Assets:Cash (old)` means just that. A plain account is left unanchored so that, as in
+/// hledger, its subaccounts count towards it.
+fn account_pattern(account: &str, is_glob: bool) -> String {
+    if is_glob {
+        return glob_regex(account);
+    }
+    let mut regex = String::new();
+    for c in account.chars() {
+        push_literal(&mut regex, c);
+    }
+    regex
+}
+
+impl Placeholder {
+    /// Resolve aliases in the account and normalize it as `options` ask.
+    fn canonicalize(&mut self, options: &BalanceOptions) {
+        self.account = options.canonical_account(&self.account);
+        self.pattern = account_pattern(&self.account, self.is_glob());
+    }
+
+    /// A `*` or `?` in the account, as in `<<Expenses:Food*>>`, makes the placeholder the
+    /// combined balance of every account the glob matches.
+    fn is_glob(&self) -> bool {
+        self.account.contains(['*', '?'])
+    }
+
+    fn rounded(&self, balance: i64) -> i64 {
+        match self.round {
+            Some(step) => (balance as f64 / step as f64).round() as i64 * step,
+            None => balance,
+        }
+    }
+
+    /// Arguments for a balance report that prints one bare amount per top-level account and no
+    /// total line. Clipping to depth 1 means the lines never overlap, so their sum is the balance
+    /// of everything matched, however the accounts are nested.
+    fn balance_args(&self, program: &str) -> Vec<&str> {
+        let mut args = vec!["bal", self.pattern.as_str()];
+        args.extend(self.query.iter().map(|q| q.as_str()));
+        if let Some(period) = &self.period {
+            args.extend(["-p", period.as_str()]);
+        }
+        if let Some(commodity) = &self.convert {
+            args.extend(["-X", commodity.as_str()]);
+        }
+        let format = match program {
+            "ledger" => "%(display_total)\n",
+            _ => "%(total)",
+        };
+        args.extend(["--no-total", "--depth", "1", "--format", format]);
+        args
+    }
+
+    /// Arguments for a register report of the matching postings. hledger writes CSV with a header
+    /// row; ledger writes one line per posting.
+    fn count_args(&self, program: &str) -> Vec<&str> {
+        let mut args = vec!["reg", self.pattern.as_str()];
+        args.extend(self.query.iter().map(|q| q.as_str()));
+        if let Some(period) = &self.period {
+            args.extend(["-p", period.as_str()]);
+        }
+        match program {
+            "ledger" => args.extend(["--format", "%(account)\n"]),
+            _ => args.extend(["-O", "csv"]),
+        }
+        args
+    }
+}
+
+impl TryFrom<&str> for Placeholder {
+    type Error = LedgerError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut split = value.split('|');
+        let account = split.next().unwrap_or_default().trim().to_string();
+        if account.is_empty() {
+            return Err(LedgerError::Misc(format!(
+                "Placeholder <<{}>> is missing an account",
+                value
+            )));
+        }
+        let mut query = Vec::new();
+        let mut period = None;
+        let mut round = None;
+        let mut count = false;
+        let mut convert = None;
+        for modifier in split {
+            if modifier.trim() == "count" {
+                count = true;
+                continue;
+            }
+            match modifier.trim().split_once('=') {
+                Some(("query", terms)) => query.extend(terms.split_whitespace().map(String::from)),
+                Some(("period", p)) => period = Some(p.trim().to_string()),
+                Some(("in", commodity)) if !commodity.trim().is_empty() => {
+                    convert = Some(commodity.trim().to_string())
+                }
+                Some(("round", units)) => {
+                    let step = units
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .map(|u| (u * 100.0).round() as i64)
+                        .filter(|step| *step > 0)
+                        .ok_or(LedgerError::Misc(format!(
+                            "round= in placeholder <<{}>> needs a positive number of units",
+                            value
+                        )))?;
+                    round = Some(step);
+                }
+                _ => {
+                    return Err(LedgerError::Misc(format!(
+                        "Unknown modifier {} in placeholder <<{}>>",
+                        modifier, value
+                    )))
+                }
+            }
+        }
+        if count && (round.is_some() || convert.is_some()) {
+            return Err(LedgerError::Misc(format!(
+                "Placeholder <<{}>> cannot both count postings and round or convert",
+                value
+            )));
+        }
+        Ok(Self {
+            pattern: account.clone(),
+            account,
+            query,
+            period,
+            round,
+            count,
+            convert,
+        })
+    }
+}
+
+/// Parse one line of balance report output, such as `$-1,234.56`, into cents.
+fn parse_balance_line(line: &[u8], options: &BalanceOptions) -> Option<i64> {
+    let balance_bytes: Vec<u8> = line
+        .iter()
+        .filter_map(|c| match char::from(*c) {
+            '0'..='9' | '-' => Some(*c),
+            d if d == options.decimal_mark.decimal() => Some(b'.'),
+            _ => None,
+        })
+        .collect();
+    let balance_str = Utf8Decoding::Lossy.decode(&balance_bytes).ok()?;
+    let balance_f64: f64 = balance_str.parse().ok()?;
+    Some((balance_f64 * 100.0).round() as i64)
+}
+
+/// A report as hledger/ledger printed it, along with the number read from it where that is
+/// unambiguous.
+#[derive(Debug)]
+pub struct BalanceReport {
+    /// The balance in cents, or the posting count for a `count` placeholder. `None` when the
+    /// report is not a single amount, such as one holding several commodities.
+    pub value: Option<i64>,
+    /// The report's output, or the amount from the balance cache when that answered instead
+    pub raw: String,
+}
+
+impl BalanceReport {
+    fn value_for(&self, account: &str) -> Result<i64, LedgerError> {
+        self.value.ok_or(LedgerError::Misc(format!(
+            "Could not parse balance for account {}",
+            account
+        )))
+    }
+}
+
+fn get_balance(
+    placeholder: &Placeholder,
+    options: &BalanceOptions,
+) -> Result<BalanceReport, LedgerError> {
+    if placeholder.count {
+        return query_count(placeholder, options);
+    }
+    let is_plain = placeholder.query.is_empty()
+        && placeholder.period.is_none()
+        && placeholder.convert.is_none()
+        && !placeholder.is_glob();
+    let cached = match &options.cache {
+        Some(cache) if is_plain => cache.get(&placeholder.account, options.fold_case_accounts),
+        _ => None,
+    };
+    let report = match cached {
+        Some(balance) => BalanceReport {
+            value: Some(balance),
+            raw: format_amount(balance, ""),
+        },
+        None if options.strict => {
+            return Err(LedgerError::Misc(format!(
+                "No balance for {} in the balance cache",
+                placeholder.account
+            )))
+        }
+        None => query_balance(placeholder, options)?,
+    };
+    if let (true, Some(balance)) = (is_plain, report.value) {
+        options
+            .resolved
+            .borrow_mut()
+            .insert(placeholder.account.clone(), balance);
+    }
+    Ok(report)
+}
+
+/// Run a report with hledger, or ledger if hledger cannot be started, returning its stdout and
+/// which program produced it.
+fn run_report<'a>(
+    options: &BalanceOptions,
+    valued: bool,
+    args: impl Fn(&'static str) -> Vec<&'a str>,
+) -> Result<(Vec<u8>, &'static str), LedgerError> {
+    let timed_out = |program: &str| LedgerError::Timeout {
+        program: program.to_string(),
+        seconds: options.timeout.unwrap_or_default().as_secs(),
+    };
+    match options.run("hledger", &args("hledger"), valued) {
+        Ok(output) => Ok((output.stdout, "hledger")),
+        Err(e) if e.kind() == ErrorKind::TimedOut => Err(timed_out("hledger")),
+        Err(_) => match options.run("ledger", &args("ledger"), valued) {
+            Ok(output) => Ok((output.stdout, "ledger")),
+            Err(e) if e.kind() == ErrorKind::TimedOut => Err(timed_out("ledger")),
+            Err(e) => Err(LedgerError::Misc(format!(
+                "Failed to execute hledger and ledger commands. Are they installed?: {}",
+                e
+            ))),
+        },
+    }
+}
+
+fn query_count(
+    placeholder: &Placeholder,
+    options: &BalanceOptions,
+) -> Result<BalanceReport, LedgerError> {
+    let (stdout, program) = run_report(options, false, |program| placeholder.count_args(program))?;
+    let lines = stdout
+        .split(|c| *c == b'\n')
+        .filter(|line| !line.trim_ascii().is_empty())
+        .count();
+    // hledger's CSV starts with a header row
+    let postings = match program {
+        "hledger" => lines.saturating_sub(1),
+        _ => lines,
+    };
+    Ok(BalanceReport {
+        value: Some(postings as i64),
+        raw: String::from_utf8_lossy(&stdout).into_owned(),
+    })
+}
+
+/// Every account declared or posted to in the journal, as `hledger accounts` or `ledger accounts`
+/// lists them.
+pub fn journal_accounts(options: &BalanceOptions) -> Result<BTreeSet<String>, LedgerError> {
+    let (stdout, _) = run_report(options, false, |_| vec!["accounts"])?;
+    Ok(String::from_utf8_lossy(&stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// The commodity written on one line of balance report output: everything that is not part of
+/// the number.
+fn line_commodity(line: &str, options: &BalanceOptions) -> String {
+    line.chars()
+        .filter(|c| {
+            !(c.is_ascii_digit()
+                || c.is_whitespace()
+                || *c == '-'
+                || *c == options.decimal_mark.decimal()
+                || *c == options.decimal_mark.group())
+        })
+        .collect()
+}
+
+fn query_balance(
+    placeholder: &Placeholder,
+    options: &BalanceOptions,
+) -> Result<BalanceReport, LedgerError> {
+    let valued = placeholder.convert.is_some();
+    let (stdout, _) = run_report(options, valued, |program| placeholder.balance_args(program))?;
+    let raw = String::from_utf8_lossy(&stdout).into_owned();
+    let value = sum_balance_lines(&raw, options);
+    Ok(BalanceReport { value, raw })
+}
+
+/// The total of a `balance_args` report, one amount per line, or `None` if any line is not a
+/// number or the lines are in more than one commodity.
+fn sum_balance_lines(raw: &str, options: &BalanceOptions) -> Option<i64> {
+    let lines: Vec<&str> = raw.lines().filter(|line| !line.trim().is_empty()).collect();
+    // Amounts in different commodities cannot be added up. A bare `0` has no commodity and fits
+    // with any.
+    let commodities: HashSet<String> = lines
+        .iter()
+        .map(|line| line_commodity(line, options))
+        .filter(|commodity| !commodity.is_empty())
+        .collect();
+    match commodities.len() {
+        0 | 1 => lines
+            .iter()
+            .map(|line| parse_balance_line(line.as_bytes(), options))
+            .sum(),
+        _ => None,
+    }
+}
+
+/// The all-time balance report of `account`, with aliases and normalization applied, for code
+/// that wants to read hledger/ledger's output itself.
+pub fn balance_report(
+    account: &str,
+    options: &BalanceOptions,
+) -> Result<BalanceReport, LedgerError> {
+    let mut placeholder = Placeholder {
+        account: account.to_string(),
+        pattern: account.to_string(),
+        query: Vec::new(),
+        period: None,
+        round: None,
+        count: false,
+        convert: None,
+    };
+    placeholder.canonicalize(options);
+    get_balance(&placeholder, options)
+}
+
+/// The all-time balance of `account`, in cents, with aliases and normalization applied.
+pub fn account_balance(account: &str, options: &BalanceOptions) -> Result<i64, LedgerError> {
+    balance_report(account, options)?.value_for(account)
+}
+
+/// Check that every `<<...>>` placeholder in `text` is closed on its own line and is a valid
+/// placeholder, without looking up any balances. `first_line` is the template line `text` starts
+/// on, for error messages.
+pub fn check_placeholders(text: &str, first_line: usize) -> Result<(), LedgerError> {
+    for (i, line) in text.lines().enumerate() {
+        let at_line = |e: LedgerError| LedgerError::Misc(format!("line {}: {}", first_line + i, e));
+        let mut rest = line;
+        while let Some(start) = rest.find("<<") {
+            let after = &rest[start + 2..];
+            let Some(end) = after.find(">>") else {
+                return Err(at_line(LedgerError::Misc(String::from(
+                    "<< is not closed by >> on the same line",
+                ))));
+            };
+            Placeholder::try_from(&after[..end]).map_err(at_line)?;
+            rest = &after[end + 2..];
+        }
+    }
+    Ok(())
+}
+
+/// Fill in every `<<Account|modifiers>>` placeholder in `template_str` with its balance in cents.
+/// Each distinct placeholder is looked up once, however often it appears.
+pub fn render_balances(
+    template_str: &str,
+    options: &BalanceOptions,
+) -> Result<String, LedgerError> {
+    let mut balances: HashMap<String, String> = HashMap::new();
+    substitute(template_str, |raw| {
+        if let Some(balance) = balances.get(raw) {
+            return Ok(balance.clone());
+        }
+        let mut placeholder = Placeholder::try_from(raw)?;
+        placeholder.canonicalize(options);
+        let balance = placeholder
+            .rounded(get_balance(&placeholder, options)?.value_for(&placeholder.account)?)
+            .to_string();
+        balances.insert(raw.to_string(), balance.clone());
+        Ok(balance)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(decimal_mark: DecimalMark) -> BalanceOptions {
+        BalanceOptions {
+            journal: PathBuf::new(),
+            show_command: false,
+            decimal_mark,
+            aliases: AccountAliases::default(),
+            normalize_accounts: None,
+            account_separator: ':',
+            cache: None,
+            strict: false,
+            price_db: None,
+            infer_market_prices: false,
+            fold_case_accounts: false,
+            retries: 0,
+            timeout: None,
+            resolved: RefCell::default(),
+            on_event: None,
+        }
+    }
+
+    #[test]
+    fn parse_balance_line_drops_group_separators() {
+        let options = options(DecimalMark::Period);
+        assert_eq!(parse_balance_line(b"$1,234.56", &options), Some(123456));
+        assert_eq!(
+            parse_balance_line(b"           $1,234,567.89  Assets:Bank", &options),
+            Some(123456789)
+        );
+    }
+
+    #[test]
+    fn parse_balance_line_reads_negative_grouped_values() {
+        assert_eq!(
+            parse_balance_line(b"$-1,234.56", &options(DecimalMark::Period)),
+            Some(-123456)
+        );
+        assert_eq!(
+            parse_balance_line(b"-1.234,56 EUR", &options(DecimalMark::Comma)),
+            Some(-123456)
+        );
+    }
+
+    #[test]
+    fn parse_balance_line_without_a_number_is_none() {
+        let options = options(DecimalMark::Period);
+        assert_eq!(parse_balance_line(b"", &options), None);
+        assert_eq!(parse_balance_line(b"--------------------", &options), None);
+        assert_eq!(parse_balance_line(b"Assets:Bank", &options), None);
+    }
+
+    #[test]
+    fn account_pattern_escapes_plain_accounts() {
+        assert_eq!(account_pattern("Assets:Checking", false), "Assets:Checking");
+        assert_eq!(
+            account_pattern("Assets:Cash (old)", false),
+            "Assets:Cash \\(old\\)"
+        );
+        assert_eq!(
+            account_pattern("Income:C++.Jobs", false),
+            "Income:C\\+\\+\\.Jobs"
+        );
+    }
+
+    #[test]
+    fn glob_regex_escapes_everything_but_wildcards() {
+        assert_eq!(
+            glob_regex("Assets:Cash (old)*"),
+            "^Assets:Cash \\(old\\).*$"
+        );
+        assert_eq!(glob_regex("Expenses:Food?"), "^Expenses:Food.$");
+        assert_eq!(glob_regex("Income:$[1].+"), "^Income:\\$\\[1\\]\\.\\+$");
+    }
+
+    #[test]
+    fn glob_regex_is_anchored_at_both_ends() {
+        assert_eq!(glob_regex("*"), "^.*$");
+        assert_eq!(glob_regex("Assets:*:Cash"), "^Assets:.*:Cash$");
+        assert_eq!(account_pattern("Assets:*", true), "^Assets:.*$");
+    }
+
+    #[test]
+    fn balance_args_clip_to_depth_one_without_a_total() {
+        let mut placeholder = Placeholder::try_from("Expenses:*").unwrap();
+        placeholder.canonicalize(&options(DecimalMark::Period));
+        let args = placeholder.balance_args("hledger");
+        assert_eq!(args[..2], ["bal", "^Expenses:.*$"]);
+        assert!(args.windows(3).any(|w| w == ["--no-total", "--depth", "1"]));
+    }
+
+    #[test]
+    fn sum_balance_lines_adds_up_each_top_level_account() {
+        let options = options(DecimalMark::Period);
+        assert_eq!(
+            sum_balance_lines("$1,200.00\n$-50.25\n\n$0.75\n", &options),
+            Some(115050)
+        );
+        assert_eq!(sum_balance_lines("0\n$12.00\n", &options), Some(1200));
+        assert_eq!(sum_balance_lines("", &options), Some(0));
+    }
+
+    #[test]
+    fn sum_balance_lines_refuses_mixed_commodities_and_junk() {
+        let options = options(DecimalMark::Period);
+        assert_eq!(sum_balance_lines("$12.00\n10.00 EUR\n", &options), None);
+        assert_eq!(sum_balance_lines("$12.00\nAssets:Bank\n", &options), None);
+    }
+
+    #[test]
+    fn cache_folds_case_only_when_asked() {
+        let path = std::env::temp_dir().join(format!("ptatemp-cache-{}", std::process::id()));
+        std::fs::write(&path, "Assets:Checking\t12.50\nassets:checking\t1.00\n").unwrap();
+        let cache = BalanceCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cache.get("Assets:Checking", false), Some(1250));
+        assert_eq!(cache.get("ASSETS:CHECKING", false), None);
+        assert_eq!(cache.get("ASSETS:CHECKING", true), Some(1250));
+        assert_eq!(cache.get("assets:checking", true), Some(100));
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::account::{normalize_account, AccountCase};
+use crate::amount::{format_amount, Amount};
+use crate::format::DisplayFormat;
+use crate::transaction::{split_description, LineItem, Transaction};
+
+/// The beancount currency for a commodity. Beancount currencies are upper case codes, so the
+/// common symbols are translated and anything else is upper cased.
+pub fn currency(commodity: &str) -> String {
+    match commodity {
+        "$" => String::from("USD"),
+        "€" => String::from("EUR"),
+        "£" => String::from("GBP"),
+        "¥" => String::from("JPY"),
+        c => c.to_uppercase(),
+    }
+}
+
+/// A beancount account: every `:` segment capitalized and spaces, which beancount does not allow,
+/// replaced with `-`.
+pub fn account(account: &str) -> String {
+    normalize_account(account, AccountCase::Capitalize).replace(' ', "-")
+}
+
+fn amount(amount: &Amount) -> String {
+    format_amount(amount.value, &currency(&amount.commodity))
+}
+
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub struct BeancountLineItem<'a> {
+    line_item: &'a LineItem,
+}
+
+impl Display for BeancountLineItem<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line_item = self.line_item;
+        // Beancount has no virtual postings, so they are kept only as comments
+        if !line_item.is_real {
+            write!(f, "; [{}]", line_item.account)?;
+        } else {
+            write!(f, "{}", account(&line_item.account))?;
+        }
+        if line_item.elided {
+            return Ok(());
+        }
+        write!(f, "  {}", amount(&line_item.amount()))?;
+        match &line_item.price {
+            Some(price) if line_item.price_is_total => write!(f, " @@ {}", amount(price)),
+            Some(price) => write!(f, " @ {}", amount(price)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A transaction in beancount syntax: a `YYYY-MM-DD * "payee" "narration"` header, tags as
+/// metadata, and one posting per line with the currency after the number.
+pub struct BeancountTransaction<'a> {
+    transaction: &'a Transaction,
+    format: &'a DisplayFormat,
+}
+
+impl Display for BeancountTransaction<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let transaction = self.transaction;
+        let header = match split_description(&transaction.desc) {
+            (payee, Some(note)) => format!("{} {}", quoted(payee), quoted(note)),
+            (narration, None) => quoted(narration),
+        };
+        write!(f, "{} * {}", transaction.date.format("%Y-%m-%d"), header)?;
+        for (key, value) in &transaction.tags {
+            write!(f, "\n{}{}: {}", self.format.indent, key, quoted(value))?;
+        }
+        for line_item in &transaction.line_items {
+            write!(
+                f,
+                "\n{}{}",
+                self.format.indent,
+                BeancountLineItem { line_item }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl BeancountTransaction<'_> {
+    /// See `Transaction::write_to`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "{}", self)
+    }
+}
+
+impl Transaction {
+    /// Lay out the transaction as beancount rather than ledger. Only `format.indent` is used.
+    pub fn display_beancount<'a>(&'a self, format: &'a DisplayFormat) -> BeancountTransaction<'a> {
+        BeancountTransaction {
+            transaction: self,
+            format,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+    use crate::transaction::TransactionBuilder;
+
+    fn beancount(builder: TransactionBuilder) -> String {
+        let date = Local.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        let transaction = builder.date(date).balance().unwrap();
+        transaction
+            .display_beancount(&DisplayFormat::default())
+            .to_string()
+    }
+
+    fn posting(line: &str) -> String {
+        BeancountLineItem {
+            line_item: &line.parse().unwrap(),
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn currencies_are_upper_case_codes() {
+        assert_eq!(currency("$"), "USD");
+        assert_eq!(currency("€"), "EUR");
+        assert_eq!(currency("aapl"), "AAPL");
+    }
+
+    #[test]
+    fn accounts_are_capitalized_without_spaces() {
+        assert_eq!(
+            account("assets:big bank:checking"),
+            "Assets:Big-bank:Checking"
+        );
+    }
+
+    #[test]
+    fn virtual_postings_become_comments() {
+        assert_eq!(
+            posting("[Budget:Food]  $-5.00"),
+            "; [Budget:Food]  -5.00 USD"
+        );
+    }
+
+    #[test]
+    fn prices_keep_their_kind() {
+        assert_eq!(
+            posting("assets:brokerage  3 AAPL @ $150.00"),
+            "Assets:Brokerage  3.00 AAPL @ 150.00 USD"
+        );
+        assert_eq!(
+            posting("assets:brokerage  3 AAPL @@ $450.00"),
+            "Assets:Brokerage  3.00 AAPL @@ 450.00 USD"
+        );
+    }
+
+    #[test]
+    fn a_transaction_has_a_header_metadata_and_postings() {
+        let builder = TransactionBuilder::new()
+            .payee_note("Grocer \"Co\"", Some("weekly"))
+            .tag("receipt", "r-12")
+            .try_add_line("expenses:food  $12.50")
+            .unwrap()
+            .try_add_line("assets:checking")
+            .unwrap();
+        assert_eq!(
+            beancount(builder),
+            concat!(
+                "2024-03-01 * \"Grocer \\\"Co\\\"\" \"weekly\"\n",
+                "    receipt: \"r-12\"\n",
+                "    Expenses:Food  12.50 USD\n",
+                "    Assets:Checking",
+            )
+        );
+    }
+
+    #[test]
+    fn a_description_without_a_note_is_the_narration() {
+        let builder = TransactionBuilder::new()
+            .desc("Rent")
+            .try_add_line("Expenses:Rent  $1000.00")
+            .unwrap()
+            .try_add_line("Assets:Checking  $-1000.00")
+            .unwrap();
+        assert!(beancount(builder).starts_with("2024-03-01 * \"Rent\"\n"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use crate::amount::commodity_is_prefix;
+
+/// How amounts of one commodity are written, so generated postings match the rest of a journal
+/// and `hledger print` leaves them alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommodityStyle {
+    /// The symbol goes before the number, as in `$1.00`, rather than after, as in `1.00 EUR`
+    pub prefix: bool,
+    /// A space separates the symbol from the number
+    pub spaced: bool,
+    /// Digits after the decimal mark. Fewer than two rounds the stored hundredths.
+    pub decimal_places: usize,
+}
+
+impl CommodityStyle {
+    /// The style `format_amount` has always used: symbols such as `$` before the number with no
+    /// space, codes such as `EUR` after it with one, and two decimal places.
+    pub fn guess(commodity: &str) -> Self {
+        let prefix = commodity_is_prefix(commodity);
+        Self {
+            prefix,
+            spaced: !prefix,
+            decimal_places: 2,
+        }
+    }
+
+    /// Write `value` hundredths of `commodity` in this style.
+    pub fn format(&self, value: i64, commodity: &str) -> String {
+        let number = format!("{:.*}", self.decimal_places, value as f64 / 100.0);
+        let space = if self.spaced && !commodity.is_empty() {
+            " "
+        } else {
+            ""
+        };
+        if self.prefix {
+            format!("{}{}{}", commodity, space, number)
+        } else {
+            format!("{}{}{}", number, space, commodity)
+        }
+    }
+}
+
+/// A registry of `CommodityStyle`s. Commodities without an entry fall back to
+/// `CommodityStyle::guess`.
+#[derive(Debug, Clone)]
+pub struct CommodityStyles {
+    styles: HashMap<String, CommodityStyle>,
+}
+
+impl Default for CommodityStyles {
+    /// Built-in styles for common currency symbols, including `¥` with no minor unit.
+    fn default() -> Self {
+        let mut styles = Self {
+            styles: HashMap::new(),
+        };
+        for symbol in ["$", "£", "€"] {
+            styles.insert(symbol, CommodityStyle::guess(symbol));
+        }
+        styles.insert(
+            "¥",
+            CommodityStyle {
+                decimal_places: 0,
+                ..CommodityStyle::guess("¥")
+            },
+        );
+        styles
+    }
+}
+
+impl CommodityStyles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<S>(&mut self, commodity: S, style: CommodityStyle)
+    where
+        S: ToString,
+    {
+        self.styles.insert(commodity.to_string(), style);
+    }
+
+    pub fn get(&self, commodity: &str) -> CommodityStyle {
+        self.styles
+            .get(commodity)
+            .copied()
+            .unwrap_or_else(|| CommodityStyle::guess(commodity))
+    }
+
+    pub fn format(&self, value: i64, commodity: &str) -> String {
+        self.get(commodity).format(value, commodity)
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use pta_template_engine::alias::AccountAliases;
+use pta_template_engine::commodity::{CommodityStyle, CommodityStyles};
+
+use crate::LedgerError;
+
+/// Settings read from the JSON config file, e.g.
+///
+/// ```json
+/// {
+///   "aliases": { "chk": "Assets:Bank:Checking" },
+///   "commodities": { "EUR": { "position": "prefix", "spaced": true, "decimal_places": 2 } },
+///   "template_dir": "/home/me/ledger/templates"
+/// }
+/// ```
+///
+/// Each field of a commodity style is optional and defaults to how ptatemp would otherwise write
+/// that commodity.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub aliases: AccountAliases,
+    pub commodity_styles: CommodityStyles,
+    /// Where `--template NAME` is looked up; see `--template-dir`
+    pub template_dir: Option<PathBuf>,
+}
+
+fn parse_commodity_style(
+    commodity: &str,
+    value: &serde_json::Value,
+) -> Result<CommodityStyle, LedgerError> {
+    let invalid = |what: &str| {
+        LedgerError::Misc(format!(
+            "Config commodity {} has an invalid {}",
+            commodity, what
+        ))
+    };
+    let mut style = CommodityStyle::guess(commodity);
+    let object = value.as_object().ok_or_else(|| invalid("style"))?;
+    if let Some(position) = object.get("position") {
+        style.prefix = match position.as_str() {
+            Some("prefix") => true,
+            Some("suffix") => false,
+            _ => return Err(invalid("position; use \"prefix\" or \"suffix\"")),
+        };
+    }
+    if let Some(spaced) = object.get("spaced") {
+        style.spaced = spaced.as_bool().ok_or_else(|| invalid("spaced"))?;
+    }
+    if let Some(places) = object.get("decimal_places") {
+        style.decimal_places = places
+            .as_u64()
+            .and_then(|p| usize::try_from(p).ok())
+            .ok_or_else(|| invalid("decimal_places"))?;
+    }
+    Ok(style)
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/ptatemp/config.json`, falling back to `~/.config/ptatemp/config.json`
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join("ptatemp").join("config.json"))
+    }
+
+    /// Load the config at `path`, or at the default location when `path` is `None`. A missing
+    /// default config is not an error, but a missing explicit one is.
+    pub fn load(path: Option<&Path>) -> Result<Self, LedgerError> {
+        let contents = match path {
+            Some(p) => std::fs::read_to_string(p)?,
+            None => match Self::default_path().map(std::fs::read_to_string) {
+                Some(Ok(c)) => c,
+                Some(Err(e)) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => return Ok(Self::default()),
+            },
+        };
+        let json: serde_json::Value = serde_json::from_str(&contents)?;
+        let mut config = Self::default();
+        if let Some(aliases) = json.get("aliases") {
+            let aliases = aliases.as_object().ok_or(LedgerError::Misc(String::from(
+                "Config key `aliases` must be an object of code to account",
+            )))?;
+            for (code, full) in aliases {
+                let full = full.as_str().ok_or(LedgerError::Misc(format!(
+                    "Config alias {} must map to an account name",
+                    code
+                )))?;
+                config.aliases.insert(code, full);
+            }
+        }
+        if let Some(commodities) = json.get("commodities") {
+            let commodities = commodities
+                .as_object()
+                .ok_or(LedgerError::Misc(String::from(
+                    "Config key `commodities` must be an object of commodity to style",
+                )))?;
+            for (commodity, style) in commodities {
+                config
+                    .commodity_styles
+                    .insert(commodity, parse_commodity_style(commodity, style)?);
+            }
+        }
+        if let Some(dir) = json.get("template_dir") {
+            let dir = dir.as_str().ok_or(LedgerError::Misc(String::from(
+                "Config key `template_dir` must be a path",
+            )))?;
+            config.template_dir = Some(PathBuf::from(dir));
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::Path;
+
+use pta_template_engine::journal::entries;
+use pta_template_engine::transaction::Transaction;
+
+/// Lines of the journal's tail shown above the appended text.
+const CONTEXT_LINES: usize = 3;
+
+/// A unified diff that appends `appended` to the end of `journal`.
+pub fn append_diff(path: &Path, journal: &str, appended: &str) -> String {
+    let old: Vec<&str> = journal.lines().collect();
+    let new: Vec<&str> = appended.lines().collect();
+    let context = &old[old.len().saturating_sub(CONTEXT_LINES)..];
+    // An empty range is numbered by the line before it, as diff does
+    let first = old.len() - context.len();
+    let old_start = first + usize::from(!context.is_empty());
+    let new_start = first + usize::from(!context.is_empty() || !new.is_empty());
+    let mut diff = format!(
+        "--- {path}\n+++ {path}\n@@ -{old_start},{old} +{new_start},{new} @@\n",
+        path = path.display(),
+        old_start = old_start,
+        new_start = new_start,
+        old = context.len(),
+        new = context.len() + new.len()
+    );
+    for line in context {
+        diff.push_str(&format!(" {}\n", line));
+    }
+    for line in new {
+        diff.push_str(&format!("+{}\n", line));
+    }
+    diff
+}
+
+/// Warn on stderr about each new transaction whose date and description already appear in
+/// `journal`, since it may have been posted before.
+pub fn warn_duplicates(journal: &str, transactions: &[Transaction]) {
+    let existing: Vec<(usize, Transaction)> = entries(journal)
+        .into_iter()
+        .filter_map(|entry| Some((entry.first_line, entry.text.parse().ok()?)))
+        .collect();
+    for transaction in transactions {
+        let duplicates = existing.iter().filter(|(_, e)| {
+            e.date().date_naive() == transaction.date().date_naive()
+                && e.desc() == transaction.desc()
+        });
+        for (line, _) in duplicates {
+            eprintln!(
+                "Warning: line {} already has a transaction dated {} described as {}",
+                line,
+                transaction.date().format("%Y-%m-%d"),
+                transaction.desc()
+            );
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::process::Command;
+
+use crate::LedgerError;
+
+/// The oldest releases that understand every flag ptatemp passes to them.
+const MIN_HLEDGER: (u32, u32) = (1, 18);
+const MIN_LEDGER: (u32, u32) = (3, 0);
+
+/// Pull `(major, minor)` out of the first word of `--version` output that looks like a version.
+fn parse_version(output: &str) -> Option<(u32, u32)> {
+    output.split_whitespace().find_map(|word| {
+        let mut parts = word
+            .trim_end_matches(',')
+            .split(|c: char| !c.is_ascii_digit());
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some((major, minor))
+    })
+}
+
+/// Check one binary, printing what was found. Returns whether it is usable.
+fn check(program: &str, minimum: (u32, u32)) -> bool {
+    let output = match Command::new(program).arg("--version").output() {
+        Ok(o) if o.status.success() => o,
+        Ok(o) => {
+            println!(
+                "{}: `{} --version` failed with {}",
+                program, program, o.status
+            );
+            return false;
+        }
+        Err(e) => {
+            println!("{}: not found ({})", program, e);
+            return false;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first_line = stdout.lines().next().unwrap_or_default().trim();
+    match parse_version(&stdout) {
+        Some(version) if version >= minimum => {
+            println!("{}: ok, {}", program, first_line);
+            true
+        }
+        Some(_) => {
+            println!(
+                "{}: too old, {}; need {}.{} or newer",
+                program, first_line, minimum.0, minimum.1
+            );
+            false
+        }
+        None => {
+            println!(
+                "{}: could not read a version from {:?}",
+                program, first_line
+            );
+            false
+        }
+    }
+}
+
+/// Report which of hledger and ledger are installed and new enough, failing if neither is.
+pub fn doctor() -> Result<(), LedgerError> {
+    let hledger = check("hledger", MIN_HLEDGER);
+    let ledger = check("ledger", MIN_LEDGER);
+    if hledger || ledger {
+        Ok(())
+    } else {
+        Err(LedgerError::Misc(String::from(
+            "Neither hledger nor ledger is usable; install one of them to resolve balance placeholders",
+        )))
+    }
+}
@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+
+/// The character set of a journal or template file. Text is always handled as UTF-8 internally,
+/// so other encodings are decoded on read and encoded again on write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    /// ISO-8859-1, where every byte is the code point of the same value
+    Latin1,
+    /// Windows-1252, which is Latin-1 with printable characters such as `€` in 0x80..=0x9F
+    Windows1252,
+}
+
+/// What Windows-1252 puts at 0x80..=0x9F. The five unassigned bytes keep their Latin-1 meaning.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl TextEncoding {
+    /// Decode `bytes` to UTF-8. Only `Utf8` can fail, on invalid input; every byte means something
+    /// in the single byte encodings.
+    pub fn decode(self, bytes: &[u8]) -> Result<Cow<'_, str>, std::str::Utf8Error> {
+        match self {
+            Self::Utf8 => std::str::from_utf8(bytes).map(Cow::Borrowed),
+            Self::Latin1 => Ok(Cow::Owned(bytes.iter().map(|b| char::from(*b)).collect())),
+            Self::Windows1252 => Ok(Cow::Owned(
+                bytes
+                    .iter()
+                    .map(|b| match b {
+                        0x80..=0x9F => WINDOWS_1252_HIGH[usize::from(b - 0x80)],
+                        b => char::from(*b),
+                    })
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Encode `text` for writing. Fails with the first character the encoding cannot represent.
+    pub fn encode(self, text: &str) -> Result<Cow<'_, [u8]>, char> {
+        let single_byte = |c: char| match self {
+            Self::Windows1252 => match WINDOWS_1252_HIGH.iter().position(|h| *h == c) {
+                Some(i) => Some(0x80 + i as u8),
+                None => u8::try_from(u32::from(c))
+                    .ok()
+                    .filter(|b| !(0x80..=0x9F).contains(b)),
+            },
+            _ => u8::try_from(u32::from(c)).ok(),
+        };
+        match self {
+            Self::Utf8 => Ok(Cow::Borrowed(text.as_bytes())),
+            Self::Latin1 | Self::Windows1252 => text
+                .chars()
+                .map(|c| single_byte(c).ok_or(c))
+                .collect::<Result<Vec<u8>, char>>()
+                .map(Cow::Owned),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_1252_maps_the_high_control_range_both_ways() {
+        let expected = [
+            (0x80, '€'),
+            (0x81, '\u{81}'),
+            (0x82, '‚'),
+            (0x83, 'ƒ'),
+            (0x84, '„'),
+            (0x85, '…'),
+            (0x86, '†'),
+            (0x87, '‡'),
+            (0x88, 'ˆ'),
+            (0x89, '‰'),
+            (0x8A, 'Š'),
+            (0x8B, '‹'),
+            (0x8C, 'Œ'),
+            (0x8D, '\u{8D}'),
+            (0x8E, 'Ž'),
+            (0x8F, '\u{8F}'),
+            (0x90, '\u{90}'),
+            (0x91, '‘'),
+            (0x92, '’'),
+            (0x93, '“'),
+            (0x94, '”'),
+            (0x95, '•'),
+            (0x96, '–'),
+            (0x97, '—'),
+            (0x98, '˜'),
+            (0x99, '™'),
+            (0x9A, 'š'),
+            (0x9B, '›'),
+            (0x9C, 'œ'),
+            (0x9D, '\u{9D}'),
+            (0x9E, 'ž'),
+            (0x9F, 'Ÿ'),
+        ];
+        for (byte, c) in expected {
+            let decoded: Vec<char> = TextEncoding::Windows1252
+                .decode(&[byte])
+                .unwrap()
+                .chars()
+                .collect();
+            assert_eq!(decoded, [c], "{:#04x}", byte);
+            let text = c.to_string();
+            let encoded = TextEncoding::Windows1252.encode(&text).unwrap();
+            assert_eq!(encoded, [byte].as_slice(), "{:?}", c);
+        }
+        assert_eq!(expected.len(), 32);
+    }
+
+    #[test]
+    fn windows_1252_keeps_unassigned_bytes_as_latin_1() {
+        for byte in [0x81, 0x8D, 0x8F, 0x90, 0x9D] {
+            let bytes = [byte];
+            let decoded = TextEncoding::Windows1252.decode(&bytes).unwrap();
+            assert_eq!(decoded, TextEncoding::Latin1.decode(&bytes).unwrap());
+            assert_eq!(decoded.chars().next(), Some(char::from(byte)));
+            assert_eq!(
+                TextEncoding::Windows1252.encode(&decoded).unwrap(),
+                [byte].as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn single_byte_encodings_round_trip_every_byte() {
+        let bytes: Vec<u8> = (0..=u8::MAX).collect();
+        for encoding in [TextEncoding::Latin1, TextEncoding::Windows1252] {
+            let text = encoding.decode(&bytes).unwrap();
+            assert_eq!(text.chars().count(), bytes.len());
+            assert_eq!(encoding.encode(&text).unwrap(), bytes.as_slice());
+        }
+    }
+
+    #[test]
+    fn encode_reports_the_first_unrepresentable_character() {
+        assert_eq!(TextEncoding::Latin1.encode("café €5"), Err('€'));
+        assert_eq!(
+            TextEncoding::Windows1252.encode("café €5").unwrap(),
+            b"caf\xE9 \x805".as_slice()
+        );
+        // `\u{80}` is Latin-1's 0x80, which Windows-1252 spends on `€`
+        assert_eq!(TextEncoding::Windows1252.encode("\u{80}"), Err('\u{80}'));
+        assert_eq!(TextEncoding::Windows1252.encode("Ā"), Err('Ā'));
+    }
+
+    #[test]
+    fn utf8_rejects_invalid_input() {
+        assert!(TextEncoding::Utf8.decode(b"caf\xE9").is_err());
+        assert_eq!(
+            TextEncoding::Utf8.decode("café".as_bytes()).unwrap(),
+            "café"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::process::ExitCode;
+
+use serde_json::json;
+
+use crate::amount::Amount;
+use crate::transaction::{LineItemBuilderError, TransactionBuilderError};
+
+#[derive(Debug)]
+pub enum LedgerError {
+    TransactionBuilder(TransactionBuilderError),
+    LineItemBuilder(LineItemBuilderError),
+    /// A rendered template line that is not a valid posting. `line_no` counts from 1 over the
+    /// template body as rendered, blank lines included.
+    ParseLine {
+        line_no: usize,
+        content: String,
+        cause: LineItemBuilderError,
+    },
+    IoError(std::io::Error),
+    MinijinjaError(minijinja::Error),
+    SerdeJsonError(serde_json::Error),
+    VarError(std::env::VarError),
+    /// Neither `--journal`, `LEDGER_FILE`, nor `~/.hledger.journal` names a journal
+    NoJournal,
+    /// hledger/ledger was killed for running longer than `--balance-timeout`
+    Timeout {
+        program: String,
+        seconds: u64,
+    },
+    Misc(String),
+}
+
+impl LedgerError {
+    /// The error as a JSON object for `--error-format json`: `error` names the variant, `kind`
+    /// the inner variant where there is one, `message` is the human-readable text, and any
+    /// structured details are included as extra fields.
+    pub fn to_json(&self) -> serde_json::Value {
+        let amounts = |amounts: &[Amount]| {
+            amounts
+                .iter()
+                .map(|a| json!({ "value": a.value, "commodity": a.commodity }))
+                .collect::<Vec<_>>()
+        };
+        let line_item_json = |e: &LineItemBuilderError| match e {
+            LineItemBuilderError::MissingAccount => {
+                json!({ "error": "LineItemBuilder", "kind": "MissingAccount" })
+            }
+            LineItemBuilderError::MissingValue => {
+                json!({ "error": "LineItemBuilder", "kind": "MissingValue" })
+            }
+            LineItemBuilderError::MissingIsReal => {
+                json!({ "error": "LineItemBuilder", "kind": "MissingIsReal" })
+            }
+            LineItemBuilderError::InvalidUtf8 => {
+                json!({ "error": "LineItemBuilder", "kind": "InvalidUtf8" })
+            }
+            LineItemBuilderError::MissingPercentBase => {
+                json!({ "error": "LineItemBuilder", "kind": "MissingPercentBase" })
+            }
+            LineItemBuilderError::ParseValue(raw) => {
+                json!({ "error": "LineItemBuilder", "kind": "ParseValue", "value": raw })
+            }
+        };
+        let mut value = match self {
+            Self::TransactionBuilder(e) => {
+                let mut value = match e {
+                    TransactionBuilderError::MissingDate => json!({ "kind": "MissingDate" }),
+                    TransactionBuilderError::MissingDesc => json!({ "kind": "MissingDesc" }),
+                    TransactionBuilderError::NotEnoughLineItems => {
+                        json!({ "kind": "NotEnoughLineItems" })
+                    }
+                    TransactionBuilderError::DoesNotBalance(residual) => {
+                        json!({ "kind": "DoesNotBalance", "residual": amounts(residual) })
+                    }
+                    TransactionBuilderError::TooManyElided => json!({ "kind": "TooManyElided" }),
+                    TransactionBuilderError::WrongLineItemCount { expected, found } => json!({
+                        "kind": "WrongLineItemCount",
+                        "expected": expected,
+                        "found": found,
+                    }),
+                    TransactionBuilderError::UnexpectedAccounts {
+                        missing,
+                        unexpected,
+                    } => json!({
+                        "kind": "UnexpectedAccounts",
+                        "missing": missing,
+                        "unexpected": unexpected,
+                    }),
+                    TransactionBuilderError::InvalidSplitWeights => {
+                        json!({ "kind": "InvalidSplitWeights" })
+                    }
+                };
+                value["error"] = json!("TransactionBuilder");
+                value
+            }
+            Self::LineItemBuilder(e) => line_item_json(e),
+            Self::ParseLine {
+                line_no,
+                content,
+                cause,
+            } => {
+                let mut value = line_item_json(cause);
+                value["error"] = json!("ParseLine");
+                value["line"] = json!(line_no);
+                value["content"] = json!(content);
+                value
+            }
+            Self::IoError(e) => json!({ "error": "IoError", "kind": format!("{:?}", e.kind()) }),
+            Self::MinijinjaError(e) => json!({
+                "error": "MinijinjaError",
+                "kind": format!("{:?}", e.kind()),
+                "line": e.line(),
+            }),
+            Self::SerdeJsonError(e) => json!({
+                "error": "SerdeJsonError",
+                "line": e.line(),
+                "column": e.column(),
+            }),
+            Self::VarError(_) => json!({ "error": "VarError" }),
+            Self::NoJournal => json!({ "error": "NoJournal" }),
+            Self::Timeout { program, seconds } => {
+                json!({ "error": "Timeout", "program": program, "seconds": seconds })
+            }
+            Self::Misc(_) => json!({ "error": "Misc" }),
+        };
+        if let Some(object) = value.as_object_mut() {
+            object.insert(String::from("message"), json!(self.to_string()));
+        }
+        value
+    }
+
+    /// The process exit code for this error. Most failures use 1; a missing journal gets its own
+    /// code so scripts can tell that setup, not the template, is at fault.
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::NoJournal => ExitCode::from(3),
+            _ => ExitCode::FAILURE,
+        }
+    }
+}
+
+impl std::fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TransactionBuilder(e) => write!(f, "could not build transaction: {}", e),
+            Self::LineItemBuilder(e) => write!(f, "could not parse posting: {}", e),
+            Self::ParseLine {
+                line_no,
+                content,
+                cause,
+            } => write!(
+                f,
+                "could not parse posting on rendered line {} ({:?}): {}",
+                line_no, content, cause
+            ),
+            Self::IoError(e) => write!(f, "{}", e),
+            Self::MinijinjaError(e) => write!(f, "template error: {:#}", e),
+            Self::SerdeJsonError(e) => write!(f, "invalid JSON: {}", e),
+            Self::VarError(e) => write!(f, "could not read environment variable: {}", e),
+            Self::NoJournal => write!(f, "No journal specified: pass --journal or set LEDGER_FILE"),
+            Self::Timeout { program, seconds } => write!(
+                f,
+                "{} was killed after running for {}s; see --balance-timeout",
+                program, seconds
+            ),
+            Self::Misc(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::TransactionBuilder(e) => Some(e),
+            Self::LineItemBuilder(e) | Self::ParseLine { cause: e, .. } => Some(e),
+            Self::IoError(e) => Some(e),
+            Self::MinijinjaError(e) => Some(e),
+            Self::SerdeJsonError(e) => Some(e),
+            Self::VarError(e) => Some(e),
+            Self::NoJournal | Self::Timeout { .. } | Self::Misc(_) => None,
+        }
+    }
+}
+
+impl From<minijinja::Error> for LedgerError {
+    fn from(value: minijinja::Error) -> Self {
+        Self::MinijinjaError(value)
+    }
+}
+
+impl From<TransactionBuilderError> for LedgerError {
+    fn from(value: TransactionBuilderError) -> Self {
+        Self::TransactionBuilder(value)
+    }
+}
+
+impl From<LineItemBuilderError> for LedgerError {
+    fn from(value: LineItemBuilderError) -> Self {
+        Self::LineItemBuilder(value)
+    }
+}
+
+impl From<std::io::Error> for LedgerError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IoError(value)
+    }
+}
+
+impl From<serde_json::Error> for LedgerError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::SerdeJsonError(value)
+    }
+}
+
+impl From<std::env::VarError> for LedgerError {
+    fn from(value: std::env::VarError) -> Self {
+        Self::VarError(value)
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::amount::format_amount;
+use crate::commodity::CommodityStyles;
+use crate::transaction::{account_needs_quotes, LineItem, Transaction};
+
+#[derive(Debug, Clone)]
+pub struct DisplayFormat {
+    /// Pad the currency symbol, sign, and integer digits of every amount to this many characters
+    /// so that the decimal points of all postings line up.
+    pub decimal_align_width: Option<usize>,
+    /// Written before every posting of a transaction. Ledger requires at least one space or tab
+    /// and nothing but whitespace; see `is_valid_indent`.
+    pub indent: String,
+    /// Write bare numbers, dropping the commodity of every amount and price, for consumers that
+    /// assume a single implicit commodity.
+    pub omit_commodity: bool,
+    /// How each commodity's amounts are written
+    pub commodity_styles: CommodityStyles,
+    /// Commodities written as another, such as `USD` as `$`. The style of the commodity written
+    /// is used.
+    pub commodity_map: HashMap<String, String>,
+    /// Follow a posting priced with `@@ TOTAL` by a `; @ PRICE` comment giving the price of one
+    /// unit.
+    pub show_unit_price: bool,
+}
+
+impl Default for DisplayFormat {
+    fn default() -> Self {
+        Self {
+            decimal_align_width: None,
+            indent: String::from("    "),
+            omit_commodity: false,
+            commodity_styles: CommodityStyles::default(),
+            commodity_map: HashMap::new(),
+            show_unit_price: false,
+        }
+    }
+}
+
+/// Whether `indent` can start a posting line: one or more spaces or tabs and nothing else.
+pub fn is_valid_indent(indent: &str) -> bool {
+    !indent.is_empty() && indent.chars().all(|c| c == ' ' || c == '\t')
+}
+
+impl DisplayFormat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decimal_align_width(self, width: usize) -> Self {
+        Self {
+            decimal_align_width: Some(width),
+            ..self
+        }
+    }
+
+    pub fn indent<S>(self, indent: S) -> Self
+    where
+        S: ToString,
+    {
+        Self {
+            indent: indent.to_string(),
+            ..self
+        }
+    }
+
+    pub fn omit_commodity(self, omit_commodity: bool) -> Self {
+        Self {
+            omit_commodity,
+            ..self
+        }
+    }
+
+    pub fn commodity_styles(self, commodity_styles: CommodityStyles) -> Self {
+        Self {
+            commodity_styles,
+            ..self
+        }
+    }
+
+    pub fn show_unit_price(self, show_unit_price: bool) -> Self {
+        Self {
+            show_unit_price,
+            ..self
+        }
+    }
+
+    /// Write amounts in `from` as `to` instead.
+    pub fn map_commodity<S>(mut self, from: S, to: S) -> Self
+    where
+        S: ToString,
+    {
+        self.commodity_map.insert(from.to_string(), to.to_string());
+        self
+    }
+
+    /// The commodity `commodity` is written as under `commodity_map`.
+    pub fn mapped_commodity<'a>(&'a self, commodity: &'a str) -> &'a str {
+        self.commodity_map
+            .get(commodity)
+            .map_or(commodity, |c| c.as_str())
+    }
+
+    /// `value` written in the style of `commodity`, after mapping it, leaving the commodity
+    /// itself off under `omit_commodity`.
+    fn styled(&self, value: i64, commodity: &str) -> String {
+        let commodity = self.mapped_commodity(commodity);
+        let shown = if self.omit_commodity { "" } else { commodity };
+        self.commodity_styles.get(commodity).format(value, shown)
+    }
+
+    fn amount(&self, value: i64, commodity: &str) -> String {
+        let amount = self.styled(value, commodity);
+        match (self.decimal_align_width, amount.split_once('.')) {
+            (Some(width), Some((integer, fraction))) => {
+                format!("{:>width$}.{}", integer, fraction, width = width)
+            }
+            _ => amount,
+        }
+    }
+}
+
+/// Column widths shared by the postings of one transaction so that they line up.
+#[derive(Debug, Clone, Copy, Default)]
+struct Columns {
+    account: usize,
+    amount: usize,
+}
+
+pub struct FormattedLineItem<'a> {
+    line_item: &'a LineItem,
+    format: &'a DisplayFormat,
+    /// Set when the posting is part of a transaction; a lone posting separates its account and
+    /// amount with two spaces and a tab instead
+    columns: Option<Columns>,
+}
+
+impl FormattedLineItem<'_> {
+    fn full_name(&self) -> String {
+        let account = if account_needs_quotes(&self.line_item.account) {
+            format!("\"{}\"", self.line_item.account)
+        } else {
+            self.line_item.account.to_owned()
+        };
+        if self.line_item.is_real {
+            account
+        } else {
+            format!("[{}]", account)
+        }
+    }
+
+    fn amount(&self) -> String {
+        self.format
+            .amount(self.line_item.value, &self.line_item.commodity)
+    }
+
+    /// The posting's own comment, preceded by its unit price under `show_unit_price`.
+    fn write_comment(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit_price = match self.line_item.unit_price() {
+            Some(unit) if self.format.show_unit_price && self.line_item.price_is_total => Some(
+                format!("@ {}", self.format.styled(unit.value, &unit.commodity)),
+            ),
+            _ => None,
+        };
+        let comment = [unit_price, self.line_item.comment.clone()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>();
+        if comment.is_empty() {
+            return Ok(());
+        }
+        write!(f, "  ; {}", comment.join(", "))
+    }
+}
+
+impl Display for FormattedLineItem<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let full_name = self.full_name();
+        if self.line_item.elided {
+            write!(f, "{}", full_name)?;
+            return self.write_comment(f);
+        }
+        match self.columns {
+            Some(columns) => write!(
+                f,
+                "{:<account$}  {:>amount$}",
+                full_name,
+                self.amount(),
+                account = columns.account,
+                amount = columns.amount
+            )?,
+            None => write!(f, "{}  \t{}", full_name, self.amount())?,
+        }
+        if let Some(price) = &self.line_item.price {
+            write!(
+                f,
+                " {} {}",
+                if self.line_item.price_is_total {
+                    "@@"
+                } else {
+                    "@"
+                },
+                self.format.styled(price.value, &price.commodity)
+            )?;
+        }
+        self.write_comment(f)
+    }
+}
+
+pub struct FormattedTransaction<'a> {
+    transaction: &'a Transaction,
+    format: &'a DisplayFormat,
+}
+
+impl Display for FormattedTransaction<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let indent = &self.format.indent;
+        writeln!(
+            f,
+            "{} {}",
+            self.transaction.date.format("%Y-%m-%d"),
+            self.transaction.desc
+        )?;
+        // Every line after the header is separated from the one before it, so the transaction
+        // ends without a newline
+        let mut separator = "";
+        for (key, value) in &self.transaction.tags {
+            write!(f, "{}{}; {}:{}", separator, indent, key, value)?;
+            separator = "\n";
+        }
+        for comment in &self.transaction.comments {
+            write!(f, "{}{}; {}", separator, indent, comment)?;
+            separator = "\n";
+        }
+        let postings: Vec<FormattedLineItem> = self
+            .transaction
+            .line_items
+            .iter()
+            .map(|l| l.display_with(self.format))
+            .collect();
+        // Widest account and amount across every posting, so the amounts form one right-aligned
+        // column
+        let columns = postings.iter().fold(Columns::default(), |columns, p| {
+            if p.line_item.elided {
+                return columns;
+            }
+            Columns {
+                account: columns.account.max(p.full_name().chars().count()),
+                amount: columns.amount.max(p.amount().chars().count()),
+            }
+        });
+        for p in postings {
+            let p = FormattedLineItem {
+                columns: Some(columns),
+                ..p
+            };
+            write!(f, "{}{}{}", separator, indent, p)?;
+            separator = "\n";
+        }
+        Ok(())
+    }
+}
+
+impl FormattedTransaction<'_> {
+    /// See `Transaction::write_to`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "{}", self)
+    }
+}
+
+/// A plain table of each posting's account and signed amount followed by the total moved, the sum
+/// of the real postings' debits in each commodity. Meant for confirming what a template did, not
+/// for a journal.
+pub struct TransactionSummary<'a> {
+    transaction: &'a Transaction,
+}
+
+impl Display for TransactionSummary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows: Vec<(String, String)> = self
+            .transaction
+            .line_items
+            .iter()
+            .map(|l| {
+                let account = if l.is_real {
+                    l.account.to_owned()
+                } else {
+                    format!("[{}]", l.account)
+                };
+                (account, format_amount(l.value, &l.commodity))
+            })
+            .collect();
+        let account_width = rows
+            .iter()
+            .map(|(a, _)| a.chars().count())
+            .max()
+            .unwrap_or(0);
+        let amount_width = rows
+            .iter()
+            .map(|(_, a)| a.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (account, amount) in &rows {
+            writeln!(
+                f,
+                "{:<account_width$}  {:>amount_width$}",
+                account,
+                amount,
+                account_width = account_width,
+                amount_width = amount_width
+            )?;
+        }
+        let mut moved: BTreeMap<&str, i64> = BTreeMap::new();
+        for line_item in self.transaction.line_items.iter().filter(|l| l.is_real) {
+            if line_item.value > 0 {
+                *moved.entry(&line_item.commodity).or_default() += line_item.value;
+            }
+        }
+        let moved = moved
+            .into_iter()
+            .map(|(commodity, value)| format_amount(value, commodity))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "Moved: {}", moved)
+    }
+}
+
+impl LineItem {
+    pub fn display_with<'a>(&'a self, format: &'a DisplayFormat) -> FormattedLineItem<'a> {
+        FormattedLineItem {
+            line_item: self,
+            format,
+            columns: None,
+        }
+    }
+}
+
+impl Transaction {
+    pub fn summary(&self) -> TransactionSummary<'_> {
+        TransactionSummary { transaction: self }
+    }
+
+    pub fn display_with<'a>(&'a self, format: &'a DisplayFormat) -> FormattedTransaction<'a> {
+        FormattedTransaction {
+            transaction: self,
+            format,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use pta_template_engine::alias::parse_alias;
+use pta_template_engine::encoding::TextEncoding;
+
+use crate::{read_encoded, LedgerError};
+
+fn parse_column_map_arg(value: &str) -> Result<(String, String), String> {
+    parse_alias(value).ok_or(String::from("expected KEY=COLUMN, such as date=1"))
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// CSV file to read, one transaction per row
+    #[arg(long, value_name = "FILE")]
+    csv: PathBuf,
+
+    /// Context key to set from each row, and the 1-based column it comes from. With --header,
+    /// COLUMN may also be a header name. Comma separated or repeated, as in
+    /// `date=1,desc=2,amount=3`
+    #[arg(
+        long,
+        required = true,
+        value_name = "KEY=COLUMN",
+        value_delimiter = ',',
+        value_parser = parse_column_map_arg
+    )]
+    map: Vec<(String, String)>,
+
+    /// The first row names the columns rather than holding a transaction
+    #[arg(long)]
+    header: bool,
+
+    /// Character that separates fields
+    #[arg(long, value_name = "CHAR", default_value_t = ',')]
+    delimiter: char,
+}
+
+/// A row of CSV fields and the 1-based line of the file it starts on.
+type Row = (usize, Vec<String>);
+
+/// Split CSV `text` into rows of fields. A field wrapped in double quotes may contain the
+/// delimiter, newlines, and `""` for a literal quote. Blank lines are skipped.
+fn parse_csv(text: &str, delimiter: char) -> Result<Vec<Row>, LedgerError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() => in_quotes = true,
+            c if in_quotes => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+            c if c == delimiter => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|f| !f.is_empty()) {
+                    rows.push((row_line, std::mem::take(&mut row)));
+                }
+                row.clear();
+                line += 1;
+                row_line = line;
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(LedgerError::Misc(format!(
+            "Quoted field is not closed by line {}",
+            line
+        )));
+    }
+    row.push(field);
+    if row.iter().any(|f| !f.is_empty()) {
+        rows.push((row_line, row));
+    }
+    Ok(rows)
+}
+
+/// The 0-based index of `column`, a 1-based number or, given `header`, a column name.
+fn column_index(column: &str, header: Option<&[String]>) -> Result<usize, LedgerError> {
+    if let Ok(number) = column.parse::<usize>() {
+        return number.checked_sub(1).ok_or(LedgerError::Misc(String::from(
+            "CSV columns are numbered from 1",
+        )));
+    }
+    header
+        .and_then(|header| header.iter().position(|name| name.trim() == column))
+        .ok_or_else(|| LedgerError::Misc(format!("No CSV column is named {}", column)))
+}
+
+/// One template context per row of the CSV file, holding the mapped columns as strings.
+pub fn contexts(
+    args: &ImportArgs,
+    encoding: TextEncoding,
+) -> Result<Vec<serde_json::Value>, LedgerError> {
+    let mut rows = parse_csv(&read_encoded(&args.csv, encoding)?, args.delimiter)?;
+    let header = if args.header && !rows.is_empty() {
+        Some(rows.remove(0).1)
+    } else {
+        None
+    };
+    let columns = args
+        .map
+        .iter()
+        .map(|(key, column)| Ok((key, column_index(column, header.as_deref())?)))
+        .collect::<Result<Vec<_>, LedgerError>>()?;
+    rows.iter()
+        .map(|(line, row)| {
+            let mut context = serde_json::Map::new();
+            for (key, index) in &columns {
+                let Some(value) = row.get(*index) else {
+                    return Err(LedgerError::Misc(format!(
+                        "The row on line {} of {} has no column {}",
+                        line,
+                        args.csv.display(),
+                        index + 1
+                    )));
+                };
+                context.insert(key.to_string(), value.trim().into());
+            }
+            Ok(context.into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(rows: &[Row]) -> Vec<Vec<&str>> {
+        rows.iter()
+            .map(|(_, row)| row.iter().map(String::as_str).collect())
+            .collect()
+    }
+
+    #[test]
+    fn quoted_fields_keep_delimiters_quotes_and_newlines() {
+        let rows = parse_csv("a,\"b, c\",\"say \"\"hi\"\"\"\n\"two\nlines\",x,y\n", ',').unwrap();
+        assert_eq!(
+            fields(&rows),
+            [
+                vec!["a", "b, c", "say \"hi\""],
+                vec!["two\nlines", "x", "y"]
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings_are_dropped() {
+        let rows = parse_csv("a;b\r\nc;d\r\n", ';').unwrap();
+        assert_eq!(fields(&rows), [vec!["a", "b"], vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn rows_report_the_line_they_start_on() {
+        let text = "date,desc\n\n2024-01-01,\"multi\nline\"\n\n\n2024-01-02,last";
+        let rows = parse_csv(text, ',').unwrap();
+        let lines: Vec<usize> = rows.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [1, 3, 7]);
+        assert_eq!(rows[2].1, ["2024-01-02", "last"]);
+    }
+
+    #[test]
+    fn an_unclosed_quote_is_an_error() {
+        assert!(parse_csv("a,\"b\nc\n", ',').is_err());
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use chrono::{Local, NaiveDate, NaiveTime, TimeZone};
+
+use crate::amount::ParseOptions;
+use crate::transaction::{
+    is_valid_tag, LineItem, LineItemBuilderError, Transaction, TransactionBuilder,
+    TransactionBuilderError,
+};
+
+/// The text of one transaction in a journal, along with where it sits in the file.
+#[derive(Debug, Clone)]
+pub struct JournalEntry<'a> {
+    /// 1-based number of the line holding the date and description
+    pub first_line: usize,
+    /// 1-based number of the transaction's last line
+    pub last_line: usize,
+    /// Byte offset of `text` within the journal
+    pub offset: usize,
+    pub text: &'a str,
+}
+
+impl JournalEntry<'_> {
+    /// The primary date from the header line, if it can be read.
+    pub fn date(&self) -> Option<NaiveDate> {
+        parse_naive_date(self.text.split_whitespace().next()?)
+    }
+}
+
+/// Find every transaction in `journal`. A transaction starts at an unindented line beginning
+/// with a digit and runs through the indented lines after it; directives, comments, and blank
+/// lines in between are skipped.
+pub fn entries(journal: &str) -> Vec<JournalEntry<'_>> {
+    let mut entries: Vec<JournalEntry> = Vec::new();
+    let mut current: Option<(usize, usize, usize, usize)> = None;
+    let mut offset = 0;
+    for (i, line) in journal.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += line.len();
+        let is_posting = line.starts_with([' ', '\t']) && !line.trim().is_empty();
+        match current.as_mut() {
+            Some((_, last_line, _, end)) if is_posting => {
+                *last_line = i + 1;
+                *end = offset;
+                continue;
+            }
+            _ => (),
+        }
+        if let Some((first_line, last_line, text_start, end)) = current.take() {
+            entries.push(JournalEntry {
+                first_line,
+                last_line,
+                offset: text_start,
+                text: &journal[text_start..end],
+            });
+        }
+        if line.starts_with(|c: char| c.is_ascii_digit()) {
+            current = Some((i + 1, i + 1, start, offset));
+        }
+    }
+    if let Some((first_line, last_line, text_start, end)) = current {
+        entries.push(JournalEntry {
+            first_line,
+            last_line,
+            offset: text_start,
+            text: &journal[text_start..end],
+        });
+    }
+    entries
+}
+
+/// The values of every `id:VALUE` tag on a transaction in `journal`, as written for
+/// `Transaction::id`. Tags are read as hledger reads them: anywhere in a comment on the header,
+/// a comment line, or a posting, separated by commas, with the tag's name being the word before
+/// the `:`.
+pub fn transaction_ids(journal: &str) -> HashSet<&str> {
+    entries(journal)
+        .iter()
+        .flat_map(|entry| entry.text.lines())
+        .filter_map(|line| line.split_once(';').map(|(_, comment)| comment))
+        .flat_map(|comment| comment.split(','))
+        .filter_map(|tag| {
+            let (key, value) = tag.split_once(':')?;
+            (key.split_whitespace().last() == Some("id")).then_some(value.trim())
+        })
+        .collect()
+}
+
+/// Insert `transaction`, the text of a transaction ending in a newline, into `journal` after the
+/// last transaction dated on or before `date`, or before the first one if all are later. A blank
+/// line separates it from its neighbours. Everything else in the journal, including comments and
+/// directives, is left exactly as it was.
+pub fn insert_sorted(journal: &str, transaction: &str, date: NaiveDate) -> String {
+    let entries = entries(journal);
+    let position = match entries
+        .iter()
+        .rposition(|e| e.date().is_some_and(|d| d <= date))
+    {
+        Some(i) => Position::After(entries[i].offset + entries[i].text.len()),
+        None => match entries.first() {
+            Some(first) => Position::Before(first.offset),
+            None => Position::After(journal.len()),
+        },
+    };
+    let mut result = String::with_capacity(journal.len() + transaction.len() + 2);
+    match position {
+        Position::After(at) => {
+            result.push_str(&journal[..at]);
+            if !result.is_empty() && !result.ends_with('\n') {
+                result.push('\n');
+            }
+            if !result.is_empty() {
+                result.push('\n');
+            }
+            result.push_str(transaction);
+            result.push_str(&journal[at..]);
+        }
+        Position::Before(at) => {
+            result.push_str(&journal[..at]);
+            result.push_str(transaction);
+            result.push('\n');
+            result.push_str(&journal[at..]);
+        }
+    }
+    result
+}
+
+enum Position {
+    After(usize),
+    Before(usize),
+}
+
+#[derive(Debug)]
+pub enum ParseTransactionError {
+    /// The first line is not `DATE [STATUS] [(CODE)] DESCRIPTION`
+    InvalidHeader(String),
+    Posting(LineItemBuilderError),
+    Builder(TransactionBuilderError),
+}
+
+impl std::fmt::Display for ParseTransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHeader(line) => write!(f, "not a transaction header: {}", line),
+            Self::Posting(e) => write!(f, "could not parse posting: {}", e),
+            Self::Builder(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseTransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidHeader(_) => None,
+            Self::Posting(e) => Some(e),
+            Self::Builder(e) => Some(e),
+        }
+    }
+}
+
+impl From<LineItemBuilderError> for ParseTransactionError {
+    fn from(value: LineItemBuilderError) -> Self {
+        Self::Posting(value)
+    }
+}
+
+impl From<TransactionBuilderError> for ParseTransactionError {
+    fn from(value: TransactionBuilderError) -> Self {
+        Self::Builder(value)
+    }
+}
+
+fn parse_naive_date(raw: &str) -> Option<NaiveDate> {
+    // A secondary date, as in `2024-01-01=2024-01-03`, is ignored
+    let primary = raw.split('=').next()?;
+    ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(primary, format).ok())
+}
+
+fn parse_date(raw: &str) -> Option<chrono::DateTime<Local>> {
+    let date = parse_naive_date(raw)?;
+    Local
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()
+}
+
+/// The key and value of a comment that is nothing but one tag, `key:value` or `key: value` with
+/// a value of one word or none. A comment with more to say, such as `Note: paid in cash`, is
+/// prose rather than a tag.
+fn parse_tag(comment: &str) -> Option<(&str, &str)> {
+    let (key, value) = comment.split_once(':')?;
+    let value = value.trim();
+    (is_valid_tag(key, value) && !value.contains(char::is_whitespace)).then_some((key, value))
+}
+
+impl Transaction {
+    /// Parse one transaction as written in a journal, such as one of `entries`. Postings may omit
+    /// their amount, which is inferred. Comment lines that are a single tag, as `parse_tag` reads
+    /// them, become tags; other comment lines and trailing posting comments are kept as comments,
+    /// and a comment on the header line is dropped. See `Transaction::without_comments` to
+    /// discard them.
+    pub fn parse_with(value: &str, options: &ParseOptions) -> Result<Self, ParseTransactionError> {
+        let mut lines = value.lines();
+        let header = lines.next().unwrap_or_default();
+        let invalid = || ParseTransactionError::InvalidHeader(header.to_string());
+        let (date, rest) = header
+            .split_once(char::is_whitespace)
+            .unwrap_or((header, ""));
+        let date = parse_date(date).ok_or_else(invalid)?;
+        let rest = rest.trim_start();
+        let rest = rest.strip_prefix(['*', '!']).unwrap_or(rest).trim_start();
+        let desc = match rest.strip_prefix('(') {
+            Some(coded) => coded.split_once(')').ok_or_else(invalid)?.1,
+            None => rest,
+        };
+        // A trailing `; comment` on the header is not part of the description
+        let desc = desc.split_once(';').map_or(desc, |(desc, _)| desc).trim();
+
+        let mut builder = TransactionBuilder::new().date(date).desc(desc);
+        for line in lines.map(str::trim).filter(|l| !l.is_empty()) {
+            if let Some(comment) = line.strip_prefix(';') {
+                let comment = comment.trim();
+                builder = match parse_tag(comment) {
+                    Some((key, value)) => builder.tag(key, value),
+                    None => builder.comment(comment),
+                };
+                continue;
+            }
+            let (posting, comment) = line.split_once(';').unwrap_or((line, ""));
+            let posting = LineItem {
+                comment: Some(comment.trim())
+                    .filter(|c| !c.is_empty())
+                    .map(String::from),
+                ..LineItem::parse_with(&posting.trim_end().replace('\t', "  "), options)?
+            };
+            builder = builder.add_line(posting);
+        }
+        Ok(builder.balance()?)
+    }
+}
+
+/// Amounts are read in dollars, as by `LineItem`'s `FromStr`.
+impl FromStr for Transaction {
+    type Err = ParseTransactionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Transaction::parse_with(s, &ParseOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posting(line: &str, comment: Option<&str>) -> LineItem {
+        LineItem {
+            comment: comment.map(String::from),
+            ..LineItem::from_str(line).unwrap()
+        }
+    }
+
+    /// `parse_with(&t.to_string())` must give back `t`, including the parts `PartialEq` ignores.
+    fn assert_round_trips(transaction: Transaction) {
+        let parsed = Transaction::from_str(&transaction.to_string()).unwrap();
+        assert_eq!(parsed, transaction);
+        assert_eq!(parsed.tags(), transaction.tags());
+        assert_eq!(parsed.comments(), transaction.comments());
+        let comments = |t: &Transaction| -> Vec<Option<String>> {
+            t.postings().map(|p| p.comment.clone()).collect()
+        };
+        assert_eq!(comments(&parsed), comments(&transaction));
+    }
+
+    fn builder() -> TransactionBuilder {
+        TransactionBuilder::new()
+            .date(parse_date("2024-03-01").unwrap())
+            .desc("Broker | monthly buy")
+    }
+
+    #[test]
+    fn round_trips_prices() {
+        assert_round_trips(
+            builder()
+                .add_line(posting("Assets:Brokerage  3 AAPL @ $150.00", None))
+                .add_line(posting("Assets:Brokerage  2 MSFT @@ $800.00", None))
+                .add_line(posting("Assets:Cash  $-1,250.00", None))
+                .balance()
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn round_trips_virtual_postings() {
+        assert_round_trips(
+            builder()
+                .add_line(posting("Expenses:Food  $12.50", None))
+                .add_line(posting("Assets:Cash  $-12.50", None))
+                .add_line(posting("[Budget:Food]  $-12.50", None))
+                .add_line(posting("[Budget:Available]  $12.50", None))
+                .balance()
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn round_trips_tags_and_comments() {
+        assert_round_trips(
+            builder()
+                .tag("id", "abc123")
+                .tag("project", "kitchen")
+                .comment("Paid at the counter")
+                .add_line(posting("Expenses:Food  $4.50", Some("latte")))
+                .add_line(posting("Assets:Cash  $-4.50", None))
+                .balance()
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn single_tags_are_tags() {
+        let transaction = Transaction::from_str(concat!(
+            "2024-03-01 Cafe\n",
+            "    ; id:abc123\n",
+            "    ; project: kitchen\n",
+            "    ; flagged:\n",
+            "    Expenses:Food  $4.50\n",
+            "    Assets:Cash\n",
+        ))
+        .unwrap();
+        let tags: Vec<(&str, &str)> = transaction
+            .tags()
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            tags,
+            vec![("id", "abc123"), ("project", "kitchen"), ("flagged", "")]
+        );
+        assert!(transaction.comments().is_empty());
+    }
+
+    #[test]
+    fn prose_comments_are_comments() {
+        let transaction = Transaction::from_str(concat!(
+            "2024-03-01 Cafe\n",
+            "    ; Note: paid in cash\n",
+            "    ; see receipt, time 10:30\n",
+            "    Expenses:Food  $4.50\n",
+            "    Assets:Cash\n",
+        ))
+        .unwrap();
+        assert!(transaction.tags().is_empty());
+        assert_eq!(
+            transaction.comments(),
+            ["Note: paid in cash", "see receipt, time 10:30"]
+        );
+        // Comments, unlike tags, are carried over to a reversal
+        let reversal = transaction
+            .reversal()
+            .date(transaction.date())
+            .balance()
+            .unwrap();
+        assert_eq!(reversal.comments(), transaction.comments());
+    }
+
+    #[test]
+    fn transaction_ids_reads_each_tag_of_a_comment() {
+        let journal = concat!(
+            "2024-03-01 Cafe  ; id:header\n",
+            "    ; id:abc, other:x\n",
+            "    ; paid, id: spaced , kind:food\n",
+            "    ; myid:not-an-id, idx:nor-this\n",
+            "    Expenses:Food  $4.50  ; id:posting\n",
+            "    Assets:Cash\n",
+        );
+        assert_eq!(
+            transaction_ids(journal),
+            HashSet::from(["header", "abc", "spaced", "posting"])
+        );
+    }
+
+    fn date(raw: &str) -> NaiveDate {
+        parse_naive_date(raw).unwrap()
+    }
+
+    const NEW: &str = "2024-02-01 New\n    Expenses:Food  $1.00\n    Assets:Cash\n";
+
+    #[test]
+    fn insert_sorted_into_an_empty_journal() {
+        assert_eq!(insert_sorted("", NEW, date("2024-02-01")), NEW);
+    }
+
+    #[test]
+    fn insert_sorted_before_the_first_entry() {
+        let journal = "; header comment\n\n2024-03-01 Later\n    a  $1\n    b\n";
+        assert_eq!(
+            insert_sorted(journal, NEW, date("2024-02-01")),
+            format!(
+                "; header comment\n\n{}\n2024-03-01 Later\n    a  $1\n    b\n",
+                NEW
+            )
+        );
+    }
+
+    #[test]
+    fn insert_sorted_after_a_last_entry_without_a_trailing_newline() {
+        let journal = "2024-01-01 Earlier\n    a  $1\n    b";
+        assert_eq!(
+            insert_sorted(journal, NEW, date("2024-02-01")),
+            format!("{}\n\n{}", journal, NEW)
+        );
+    }
+
+    #[test]
+    fn insert_sorted_keeps_comments_between_entries_in_place() {
+        let journal = concat!(
+            "2024-01-01 Earlier\n    a  $1\n    b\n",
+            "\n; about the later one\n\n",
+            "2024-03-01 Later\n    a  $1\n    b\n",
+        );
+        assert_eq!(
+            insert_sorted(journal, NEW, date("2024-02-01")),
+            format!(
+                "2024-01-01 Earlier\n    a  $1\n    b\n\n{}\n; about the later one\n\n\
+                 2024-03-01 Later\n    a  $1\n    b\n",
+                NEW
+            )
+        );
+    }
+
+    #[test]
+    fn insert_sorted_goes_after_entries_on_the_same_date() {
+        let journal = concat!(
+            "2024-02-01 First\n    a  $1\n    b\n\n",
+            "2024-02-01 Second\n    a  $1\n    b\n\n",
+            "2024-02-02 Next day\n    a  $1\n    b\n",
+        );
+        assert_eq!(
+            insert_sorted(journal, NEW, date("2024-02-01")),
+            format!(
+                "2024-02-01 First\n    a  $1\n    b\n\n\
+                 2024-02-01 Second\n    a  $1\n    b\n\n{}\n\
+                 2024-02-02 Next day\n    a  $1\n    b\n",
+                NEW
+            )
+        );
+    }
+}
@@ -0,0 +1,14 @@
+pub mod account;
+pub mod alias;
+pub mod amount;
+#[cfg(feature = "native")]
+pub mod balance;
+pub mod beancount;
+pub mod commodity;
+pub mod encoding;
+#[cfg(feature = "native")]
+pub mod error;
+pub mod format;
+pub mod journal;
+pub mod placeholder;
+pub mod transaction;
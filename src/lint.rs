@@ -0,0 +1,384 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+
+use pta_template_engine::amount::ParseOptions;
+use pta_template_engine::transaction::{
+    LineItem, LineItemBuilderError, TransactionBuilder, TransactionBuilderError,
+};
+use serde_json::json;
+
+use crate::LedgerError;
+use pta_template_engine::balance::{journal_accounts, BalanceOptions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One finding of `lint`.
+#[derive(Debug)]
+pub struct Lint {
+    pub severity: Severity,
+    /// Short name of the check that found it, such as `zero-posting`
+    pub check: &'static str,
+    /// Index of the context entry rendered, for a batch context
+    pub entry: Option<usize>,
+    /// 1-based line of the rendered template, where the finding belongs to one posting
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Lint {
+    fn new<S>(severity: Severity, check: &'static str, line: Option<usize>, message: S) -> Self
+    where
+        S: ToString,
+    {
+        Self {
+            severity,
+            check,
+            entry: None,
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "severity": self.severity.to_string(),
+            "check": self.check,
+            "entry": self.entry,
+            "line": self.line,
+            "message": self.message,
+        })
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]", self.severity, self.check)?;
+        if let Some(entry) = self.entry {
+            write!(f, " entry {}", entry)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, " line {}", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// A parsed posting and the rendered line it came from.
+type Posting = (usize, LineItem);
+
+/// Parse every non-blank line of `text`, reporting lines that are not postings. An amount that
+/// only fails for having too many decimal places gets its own check.
+fn check_parse(text: &str, options: &ParseOptions) -> (Vec<Posting>, Vec<Lint>) {
+    let mut postings = Vec::new();
+    let mut lints = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match LineItem::parse_with(line, options) {
+            Ok(posting) => postings.push((i + 1, posting)),
+            Err(LineItemBuilderError::ParseValue(raw))
+                if LineItem::parse_with(line, &options.clone().max_decimal_places(usize::MAX))
+                    .is_ok() =>
+            {
+                lints.push(Lint::new(
+                    Severity::Error,
+                    "decimal-places",
+                    Some(i + 1),
+                    format!(
+                        "{} has more than {} decimal places",
+                        raw, options.max_decimal_places
+                    ),
+                ))
+            }
+            Err(e) => lints.push(Lint::new(Severity::Error, "parse", Some(i + 1), e)),
+        }
+    }
+    (postings, lints)
+}
+
+/// Accounts with an empty segment, as in `Assets::Cash`, or with spaces around a segment.
+fn check_account_names(postings: &[Posting], separator: char) -> Vec<Lint> {
+    postings
+        .iter()
+        .filter(|(_, p)| {
+            p.account
+                .split(separator)
+                .any(|segment| segment.is_empty() || segment.trim() != segment)
+        })
+        .map(|(line, p)| {
+            Lint::new(
+                Severity::Error,
+                "account-name",
+                Some(*line),
+                format!("{:?} has an empty or padded segment", p.account),
+            )
+        })
+        .collect()
+}
+
+/// Several postings to the same account, which usually means a copy and paste slip.
+fn check_duplicate_accounts(postings: &[Posting]) -> Vec<Lint> {
+    let mut seen: HashMap<(&str, bool), usize> = HashMap::new();
+    let mut lints = Vec::new();
+    for (line, p) in postings {
+        match seen.get(&(p.account.as_str(), p.is_real)) {
+            Some(first) => lints.push(Lint::new(
+                Severity::Warning,
+                "duplicate-account",
+                Some(*line),
+                format!("{} is also posted to on line {}", p.account, first),
+            )),
+            None => {
+                seen.insert((&p.account, p.is_real), *line);
+            }
+        }
+    }
+    lints
+}
+
+/// Postings that move nothing; see `--drop-zero-postings`.
+fn check_zero_postings(postings: &[Posting]) -> Vec<Lint> {
+    postings
+        .iter()
+        .filter(|(_, p)| !p.elided && p.value == 0)
+        .map(|(line, p)| {
+            Lint::new(
+                Severity::Warning,
+                "zero-posting",
+                Some(*line),
+                format!("{} has a zero amount", p.account),
+            )
+        })
+        .collect()
+}
+
+/// Everything `TransactionBuilder::validate` objects to, apart from the date and description,
+/// which come from the command line rather than the template.
+fn check_balance(postings: &[Posting]) -> Vec<Lint> {
+    let builder =
+        TransactionBuilder::new().line_items(postings.iter().map(|(_, p)| p.to_owned()).collect());
+    builder
+        .validate()
+        .into_iter()
+        .filter(|e| {
+            !matches!(
+                e,
+                TransactionBuilderError::MissingDate | TransactionBuilderError::MissingDesc
+            )
+        })
+        .map(|e| Lint::new(Severity::Error, "balance", None, e))
+        .collect()
+}
+
+/// Accounts the journal has never seen, which hledger --strict would reject. `known` is what
+/// `journal_accounts` found.
+fn check_unknown_accounts(
+    postings: &[Posting],
+    known: Result<BTreeSet<String>, LedgerError>,
+) -> Vec<Lint> {
+    let known = match known {
+        Ok(known) => known,
+        Err(e) => {
+            return vec![Lint::new(
+                Severity::Info,
+                "unknown-account",
+                None,
+                format!("Skipped; could not list the journal's accounts: {}", e),
+            )]
+        }
+    };
+    postings
+        .iter()
+        .filter(|(_, p)| !known.contains(&p.account))
+        .map(|(line, p)| {
+            Lint::new(
+                Severity::Warning,
+                "unknown-account",
+                Some(*line),
+                format!("{} is not an account in the journal", p.account),
+            )
+        })
+        .collect()
+}
+
+/// Run every check on `text`, a rendered template, most severe findings first.
+pub fn lint(text: &str, parse: &ParseOptions, balance: &BalanceOptions) -> Vec<Lint> {
+    let (mut postings, mut lints) = check_parse(text, parse);
+    for (_, p) in &mut postings {
+        p.account = balance.canonical_account(&p.account);
+    }
+    lints.extend(check_account_names(&postings, balance.account_separator));
+    lints.extend(check_duplicate_accounts(&postings));
+    lints.extend(check_zero_postings(&postings));
+    lints.extend(check_balance(&postings));
+    lints.extend(check_unknown_accounts(&postings, journal_accounts(balance)));
+    lints.sort_by_key(|lint| std::cmp::Reverse(lint.severity));
+    lints
+}
+
+/// Write `lints` to `out`, as text or a JSON array, and fail if any of them is an error.
+pub fn report<W>(out: &mut W, lints: &[Lint], json: bool) -> Result<(), LedgerError>
+where
+    W: Write,
+{
+    if json {
+        let lints: Vec<serde_json::Value> = lints.iter().map(Lint::to_json).collect();
+        writeln!(out, "{}", serde_json::to_string_pretty(&lints)?)?;
+    } else {
+        for lint in lints {
+            writeln!(out, "{}", lint)?;
+        }
+    }
+    out.flush()?;
+    let errors = lints
+        .iter()
+        .filter(|lint| lint.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(LedgerError::Misc(format!("Lint found {} error(s)", errors)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checked(text: &str) -> (Vec<Posting>, Vec<Lint>) {
+        check_parse(text, &ParseOptions::new())
+    }
+
+    fn postings(text: &str) -> Vec<Posting> {
+        let (postings, lints) = checked(text);
+        assert!(lints.is_empty(), "{:?}", lints);
+        postings
+    }
+
+    fn checks(lints: &[Lint]) -> Vec<(&str, Option<usize>)> {
+        lints.iter().map(|lint| (lint.check, lint.line)).collect()
+    }
+
+    #[test]
+    fn parse_reports_lines_that_are_not_postings() {
+        let (postings, lints) = checked("Assets:Bank  $1.00\n\nAssets:Cash  $abc\n");
+        assert_eq!(postings.len(), 1);
+        assert_eq!(postings[0].0, 1);
+        assert_eq!(checks(&lints), [("parse", Some(3))]);
+        assert_eq!(lints[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn decimal_places_is_its_own_check() {
+        let (postings, lints) = checked("Assets:Bank  $1.005\nIncome  $-1.00\n");
+        assert_eq!(postings.len(), 1);
+        assert_eq!(checks(&lints), [("decimal-places", Some(1))]);
+        assert!(lints[0].message.contains("more than 2 decimal places"));
+    }
+
+    #[test]
+    fn account_name_flags_empty_and_padded_segments() {
+        let postings = postings("Assets::Bank  $1.00\nAssets: Cash  $1.00\nAssets:Bank  $1.00\n");
+        let lints = check_account_names(&postings, ':');
+        assert_eq!(
+            checks(&lints),
+            [("account-name", Some(1)), ("account-name", Some(2))]
+        );
+        assert!(check_account_names(&postings[2..], ':').is_empty());
+    }
+
+    #[test]
+    fn duplicate_account_points_at_the_first_posting() {
+        let postings =
+            postings("Expenses:Food  $1.00\n[Expenses:Food]  $1.00\nExpenses:Food  $2.00\n");
+        let lints = check_duplicate_accounts(&postings);
+        assert_eq!(checks(&lints), [("duplicate-account", Some(3))]);
+        assert!(lints[0].message.ends_with("on line 1"));
+    }
+
+    #[test]
+    fn zero_posting_skips_elided_amounts() {
+        let postings = postings("Expenses:Food  $0.00\nAssets:Bank\nIncome  $1.00\n");
+        assert_eq!(
+            checks(&check_zero_postings(&postings)),
+            [("zero-posting", Some(1))]
+        );
+    }
+
+    #[test]
+    fn balance_ignores_the_missing_date_and_description() {
+        let balanced = postings("Expenses:Food  $5.00\nAssets:Bank  $-5.00\n");
+        assert!(check_balance(&balanced).is_empty());
+        let unbalanced = postings("Expenses:Food  $5.00\nAssets:Bank  $-4.00\n");
+        let lints = check_balance(&unbalanced);
+        assert_eq!(checks(&lints), [("balance", None)]);
+        assert_eq!(lints[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn unknown_account_warns_or_is_skipped() {
+        let postings = postings("Expenses:Food  $5.00\nAssets:Bank  $-5.00\n");
+        let known = BTreeSet::from([String::from("Assets:Bank")]);
+        let lints = check_unknown_accounts(&postings, Ok(known));
+        assert_eq!(checks(&lints), [("unknown-account", Some(1))]);
+        assert_eq!(lints[0].severity, Severity::Warning);
+        let lints = check_unknown_accounts(
+            &postings,
+            Err(LedgerError::Misc(String::from("no hledger"))),
+        );
+        assert_eq!(checks(&lints), [("unknown-account", None)]);
+        assert_eq!(lints[0].severity, Severity::Info);
+    }
+
+    #[test]
+    fn report_writes_a_json_array_and_fails_on_errors() {
+        let lints = [
+            Lint {
+                entry: Some(2),
+                ..Lint::new(Severity::Error, "parse", Some(3), "bad line")
+            },
+            Lint::new(Severity::Warning, "zero-posting", None, "nothing moves"),
+        ];
+        let mut out = Vec::new();
+        let result = report(&mut out, &lints, true);
+        assert_eq!(result.unwrap_err().to_string(), "Lint found 1 error(s)");
+        let written: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            written,
+            json!([
+                {
+                    "severity": "error",
+                    "check": "parse",
+                    "entry": 2,
+                    "line": 3,
+                    "message": "bad line",
+                },
+                {
+                    "severity": "warning",
+                    "check": "zero-posting",
+                    "entry": null,
+                    "line": null,
+                    "message": "nothing moves",
+                },
+            ])
+        );
+        let mut out = Vec::new();
+        assert!(report(&mut out, &lints[1..], false).is_ok());
+        assert_eq!(out, b"warning[zero-posting]: nothing moves\n");
+    }
+}
@@ -1,221 +1,1623 @@
-mod transaction;
+mod config;
+mod diff;
+mod doctor;
+mod import;
+mod lint;
+mod opening;
+mod price;
+mod recur;
+mod reverse;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod template;
 
 use chrono::{Local, TimeZone};
-use clap::Parser;
-use regex::Regex;
-use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::path::Path;
-use std::{error::Error, path::PathBuf};
-use transaction::{LineItem, LineItemBuilderError, TransactionBuilder, TransactionBuilderError};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::Config;
+use pta_template_engine::account::{AccountCase, ACCOUNT_SEPARATOR};
+use pta_template_engine::alias::parse_alias;
+use pta_template_engine::amount::{
+    format_amount, parse_amount, Amount, AmountUnit, DecimalMark, ParseOptions,
+};
+use pta_template_engine::balance::{
+    account_balance, balance_report, shell_quote, BalanceCache, BalanceOptions, RunEvent,
+};
+use pta_template_engine::encoding::TextEncoding;
+use pta_template_engine::error::LedgerError;
+use pta_template_engine::format::{is_valid_indent, DisplayFormat};
+use pta_template_engine::journal::{insert_sorted, transaction_ids};
+use pta_template_engine::transaction::{
+    append_locked, is_valid_tag, render_batch, Expectation, LineItem, Transaction,
+    TransactionBuilder, TransactionBuilderError,
+};
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::OpenOptions;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use template::{FrontMatter, Template};
 
-#[derive(Debug)]
-pub enum LedgerError {
-    TransactionBuilder(TransactionBuilderError),
-    LineItemBuilder(LineItemBuilderError),
-    IoError(std::io::Error),
-    MinijinjaError(minijinja::Error),
-    Misc(String),
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum TrailingNewline {
+    /// Add a newline only when stdout is not a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
-impl std::fmt::Display for LedgerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+impl TrailingNewline {
+    fn wanted(self) -> bool {
+        match self {
+            Self::Auto => !std::io::stdout().is_terminal(),
+            Self::Always => true,
+            Self::Never => false,
+        }
     }
 }
 
-impl std::error::Error for LedgerError {}
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum OutputFormat {
+    /// ledger/hledger journal syntax
+    #[default]
+    Ledger,
+    /// beancount syntax, with currency codes after amounts. Lossy: virtual postings are
+    /// commented out
+    Beancount,
+    /// One JSON object per transaction, each on its own line
+    Json,
+}
 
-impl From<minijinja::Error> for LedgerError {
-    fn from(value: minijinja::Error) -> Self {
-        Self::MinijinjaError(value)
-    }
+/// Where `--format` sends its output: stdout, or appended to `path`.
+#[derive(Debug, Clone)]
+struct OutputTarget {
+    format: OutputFormat,
+    path: Option<PathBuf>,
+}
+
+fn parse_output_target(value: &str) -> Result<OutputTarget, String> {
+    let (format, path) = match value.split_once(':') {
+        Some((format, path)) if !path.is_empty() => (format, Some(PathBuf::from(path))),
+        Some(_) => {
+            return Err(String::from(
+                "expected FORMAT:FILE, such as json:audit.jsonl",
+            ))
+        }
+        None => (value, None),
+    };
+    Ok(OutputTarget {
+        format: OutputFormat::from_str(format, true)?,
+        path,
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum ErrorFormat {
+    /// `Error: ` followed by a sentence
+    #[default]
+    Human,
+    /// One JSON object per error; see `LedgerError::to_json`
+    Json,
 }
 
-impl From<TransactionBuilderError> for LedgerError {
-    fn from(value: TransactionBuilderError) -> Self {
-        Self::TransactionBuilder(value)
+fn parse_naive_date(value: &str) -> Result<chrono::NaiveDate, String> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|e| format!("expected a %Y-%m-%d date: {}", e))
+}
+
+fn parse_decimal_mark(value: &str) -> Result<DecimalMark, String> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => {
+            DecimalMark::try_from(c).or(Err(format!("{} is not a supported decimal mark", c)))
+        }
+        _ => Err(String::from("expected a single `.` or `,` character")),
     }
 }
 
-impl From<LineItemBuilderError> for LedgerError {
-    fn from(value: LineItemBuilderError) -> Self {
-        Self::LineItemBuilder(value)
+fn parse_alias_arg(value: &str) -> Result<(String, String), String> {
+    parse_alias(value).ok_or(String::from("expected CODE=Full:Account"))
+}
+
+fn parse_commodity_map_arg(value: &str) -> Result<(String, String), String> {
+    parse_alias(value).ok_or(String::from("expected FROM=TO, such as USD=$"))
+}
+
+fn parse_indent(value: &str) -> Result<String, String> {
+    if let Ok(count) = value.parse::<usize>() {
+        return match count {
+            0 => Err(String::from("postings need at least one space of indent")),
+            n => Ok(" ".repeat(n)),
+        };
+    }
+    if is_valid_indent(value) {
+        Ok(value.to_string())
+    } else {
+        Err(String::from(
+            "expected a number of spaces or a string of only spaces and tabs",
+        ))
     }
 }
 
-impl From<std::io::Error> for LedgerError {
-    fn from(value: std::io::Error) -> Self {
-        Self::IoError(value)
+fn parse_tag(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or(String::from("expected KEY=VALUE"))?;
+    if is_valid_tag(key, value) {
+        Ok((key.to_string(), value.to_string()))
+    } else {
+        Err(String::from(
+            "tag keys cannot contain spaces, `:` or `,`, and values cannot contain `,`",
+        ))
     }
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Write a commented example template to PATH
+    InitTemplate { path: PathBuf },
+    /// Check that hledger or ledger is installed and recent enough
+    Doctor,
+    /// Print the all-time balance of an account as ptatemp reads it
+    Balance {
+        account: String,
+        /// Print the report exactly as hledger/ledger wrote it, for balances ptatemp cannot
+        /// read as one amount, such as several commodities
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Print, or post, a transaction that undoes one already in the journal
+    Reverse(reverse::ReverseArgs),
+    /// Print, or post, a transaction that opens a new journal with the current balances of some
+    /// accounts
+    OpeningBalances(opening::OpeningArgs),
+    /// Print, or post, a `P` market price directive for a commodity
+    Price(price::PriceArgs),
+    /// Render the --template and --context given before this subcommand and report every
+    /// problem in the postings at once, rather than stopping at the first
+    Lint {
+        /// Print the findings as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Render the --template given before this subcommand once per row of a CSV file, in place
+    /// of --context, as in `ptatemp -t bank.tmpl import --csv statement.csv --map
+    /// date=1,desc=2,amount=3`
+    Import(import::ImportArgs),
+    /// Render the --template given before this subcommand once per occurrence of a schedule,
+    /// dating each transaction by its occurrence and setting `occurrence_date` in the context,
+    /// as in `ptatemp -t rent.tmpl -c '{"amount": 1200}' recur --every monthly --count 12`
+    Recur(recur::RecurArgs),
+}
+
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short = 'f', long, value_name = "FILE")]
     journal: Option<PathBuf>,
 
-    #[arg(short, long, value_name = "FILE")]
-    template: PathBuf,
+    /// Template to render. Repeat to combine the postings of several templates into one
+    /// transaction; for front matter, the first template that sets a key wins
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        required_unless_present = "template_path"
+    )]
+    template: Vec<PathBuf>,
+
+    /// Directory that `--template NAME` is looked up in, as NAME.tmpl [default:
+    /// $PTATEMP_TEMPLATE_DIR, then `template_dir` in the config, then
+    /// $XDG_CONFIG_HOME/ptatemp/templates]
+    #[arg(long, value_name = "DIR")]
+    template_dir: Option<PathBuf>,
+
+    /// Template to render, for quick entries; rendered before any --template
+    #[arg(value_name = "TEMPLATE")]
+    template_path: Option<PathBuf>,
+
+    /// Transaction description; --desc and --payee take precedence
+    #[arg(value_name = "DESC", requires = "template_path")]
+    desc_arg: Option<String>,
+
+    /// Transaction date; --date takes precedence
+    #[arg(value_name = "DATE", requires = "desc_arg")]
+    date_arg: Option<String>,
 
     #[arg(short, long)]
     date: Option<String>,
 
-    #[arg(short = 'D', long)]
-    desc: String,
+    #[arg(
+        short = 'D',
+        long,
+        conflicts_with_all = ["payee", "note"]
+    )]
+    desc: Option<String>,
+
+    /// Payee half of an hledger `payee | note` description
+    #[arg(long)]
+    payee: Option<String>,
+
+    /// Note half of an hledger `payee | note` description
+    #[arg(long, requires = "payee")]
+    note: Option<String>,
+
+    /// Only generate transactions dated on or after this `%Y-%m-%d` date
+    #[arg(long, value_name = "DATE", value_parser = parse_naive_date)]
+    since: Option<chrono::NaiveDate>,
+
+    /// Only generate transactions dated before this `%Y-%m-%d` date
+    #[arg(long, value_name = "DATE", value_parser = parse_naive_date)]
+    until: Option<chrono::NaiveDate>,
+
+    /// JSON context for the template. An array renders one transaction per entry, each of
+    /// which may set its own `date` and `desc`
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["validate_template", "context_env"]
+    )]
+    context: Option<String>,
+
+    /// Read the JSON context from environment variable VAR instead of --context, so it does not
+    /// show up in the process list
+    #[arg(long, value_name = "VAR", conflicts_with = "context")]
+    context_env: Option<String>,
+
+    /// Check each template's front matter, balance placeholders, and minijinja syntax, then exit,
+    /// without reading the journal or needing --context
+    #[arg(long)]
+    validate_template: bool,
+
+    /// Character used as the decimal mark in posting amounts and hledger output: `.` or `,`
+    #[arg(long, value_name = "CHAR", default_value = ".", value_parser = parse_decimal_mark)]
+    decimal_separator: DecimalMark,
+
+    /// Whether the numbers in postings are dollars or cents
+    #[arg(long, value_enum, default_value_t)]
+    input_amount_unit: AmountUnit,
+
+    /// Pad the integer part of every amount to WIDTH characters so decimal points line up
+    #[arg(long, value_name = "WIDTH")]
+    align_decimals: Option<usize>,
+
+    /// Indent before each posting: a number of spaces or a literal string of spaces and tabs
+    #[arg(long, default_value = "4", value_parser = parse_indent)]
+    indent: String,
+
+    /// Reject posting amounts with more fractional digits than this, such as `$1.234`
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    max_decimal_places: usize,
+
+    /// Accept transactions whose postings sum to within this many cents of zero, leaving them
+    /// unchanged
+    #[arg(long, value_name = "CENTS", default_value_t = 0)]
+    balance_tolerance: i64,
+
+    /// Resolve posting amounts written as percentages, like `18%`, against the amount in this
+    /// context key, rounded to the cent
+    #[arg(long, value_name = "KEY")]
+    percent_base: Option<String>,
+
+    /// Leave out postings whose amount renders as zero, such as a fee that did not apply. Postings
+    /// written without an amount are kept and balanced as usual
+    #[arg(long)]
+    drop_zero_postings: bool,
+
+    /// Let virtual postings sum to something other than zero, still requiring the real postings to
+    /// balance
+    #[arg(long)]
+    no_virtual_balance_check: bool,
+
+    /// Make real postings to the --source account negative, so template amounts can all be
+    /// written as positive
+    #[arg(long, requires = "source")]
+    infer_signs: bool,
+
+    /// Account money leaves under --infer-signs
+    #[arg(long, value_name = "ACCOUNT", requires = "infer_signs")]
+    source: Option<String>,
+
+    /// Fail if the templates render any virtual (bracketed) posting
+    #[arg(long, conflicts_with = "require_all_virtual")]
+    require_all_real: bool,
+
+    /// Fail if the templates render any real posting, as for budget envelope templates
+    #[arg(long)]
+    require_all_virtual: bool,
+
+    /// Write amounts in commodity FROM as commodity TO, such as `USD=$`; may be repeated
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_commodity_map_arg)]
+    commodity_map: Vec<(String, String)>,
+
+    /// Follow each posting priced with `@@ TOTAL` by a `; @ PRICE` comment giving the implied
+    /// price of one unit
+    #[arg(long)]
+    show_unit_price: bool,
+
+    /// Write amounts as bare numbers without their currency symbol or commodity
+    #[arg(long)]
+    no_currency: bool,
+
+    /// Syntax to print the transaction in: ledger, beancount, or json. FORMAT:FILE appends to
+    /// FILE instead of printing, after --post succeeds when posting. Repeat to write several
+    /// formats in one run. Without --format, ledger is printed. beancount has no virtual
+    /// postings, so they are written as comments and drop out of its balances
+    #[arg(long, value_name = "FORMAT[:FILE]", value_parser = parse_output_target)]
+    format: Vec<OutputTarget>,
+
+    /// Only print the amounts that would be inferred for postings without one
+    #[arg(long)]
+    preview_balance: bool,
+
+    /// Only print the postings left without an amount, with the amount that balances them, ready
+    /// to paste under postings already written
+    #[arg(long, conflicts_with_all = ["preview_balance", "post", "diff"])]
+    only_balancing: bool,
+
+    /// Character set of the journal and templates. Posted transactions are written in it too
+    #[arg(long, value_enum, default_value_t, global = true)]
+    journal_encoding: TextEncoding,
+
+    /// Trim whitespace around each `:`, or --account-separator, in every posting and placeholder
+    /// account and rewrite the case of each segment. Applied after aliases are resolved
+    #[arg(long, value_name = "CASE", num_args = 0..=1, default_missing_value = "capitalize")]
+    normalize_accounts: Option<AccountCase>,
+
+    /// Character between account segments, for journals that write `Assets/Checking`. Aliases,
+    /// --normalize-accounts, and lint split accounts on it; accounts are otherwise kept verbatim
+    #[arg(long, value_name = "CHAR", default_value_t = ACCOUNT_SEPARATOR)]
+    account_separator: char,
+
+    /// Print a table of each posting's account and amount, and the total moved, to stderr
+    #[arg(long)]
+    summary: bool,
+
+    /// Shell command the rendered template is piped through before its postings are parsed. It
+    /// sees the text after balances and template variables are filled in and must print one
+    /// posting per line
+    #[arg(long, value_name = "CMD")]
+    post_render_cmd: Option<String>,
+
+    /// Make environment variable NAME available to templates as `{{ env.NAME }}`. Repeatable; a
+    /// trailing `*`, as in `PTATEMP_*`, exposes every variable with that prefix. Nothing is
+    /// exposed by default. A context that has its own `env` key keeps it and sees no variables
+    #[arg(long, value_name = "NAME")]
+    expose_env: Vec<String>,
+
+    /// Look up the all-time balance of each ACCOUNT and give templates a `balances` list of
+    /// `{account, balance}` objects to loop over, with balances in cents like `<<...>>`
+    /// placeholders. A context that has its own `balances` key keeps it
+    #[arg(long, value_name = "ACCOUNT", value_delimiter = ',')]
+    balance_accounts: Vec<String>,
+
+    /// Prompt on stdin for a missing date (empty for today) or description, and for any variable
+    /// the templates use that the context does not set, instead of failing
+    #[arg(long)]
+    interactive: bool,
+
+    /// Record the templates, description, date, and context each transaction was generated from
+    /// as a `; ptatemp ...` comment line under its header, so it can be generated again. The
+    /// context is written as one line of JSON
+    #[arg(long)]
+    record_invocation: bool,
+
+    /// Print each context the template is rendered against, as pretty JSON, to stderr. Balance
+    /// placeholders are filled into the template itself, so see --show-command for those
+    #[arg(long)]
+    print_context: bool,
+
+    /// Append the transactions to the journal instead of printing them. Every transaction in a
+    /// batch is built and balanced first, so a failure leaves the journal untouched
+    #[arg(long, conflicts_with = "preview_balance")]
+    post: bool,
+
+    /// Instead of writing to the journal, print exactly what --post would write, each line quoted
+    /// so whitespace is visible
+    #[arg(long, requires = "post")]
+    show_post_bytes: bool,
+
+    /// Tag each posted transaction with `; id:HASH`, a hash of its date, description, and
+    /// postings, and skip any whose id is already in the journal, so a retried run posts nothing
+    /// twice
+    #[arg(long, requires = "post")]
+    idempotent: bool,
+
+    /// Also insert posted transactions into the SQLite database at FILE, in `transactions` and
+    /// `postings` tables created if absent. The rows are committed only if the journal is written.
+    /// Needs the `sqlite3` program
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "FILE", requires = "post")]
+    sqlite: Option<PathBuf>,
+
+    /// Instead of posting, print what --post would append as a unified diff against the journal,
+    /// and warn about transactions already in it with the same date and description
+    #[arg(long, conflicts_with_all = ["preview_balance", "post"])]
+    diff: bool,
+
+    /// Instead of a transaction, print an `account` directive for every account the template
+    /// renders, real or virtual, so a new journal passes `hledger --strict`
+    #[arg(long, conflicts_with_all = ["preview_balance", "post", "diff"])]
+    emit_account_directives: bool,
+
+    /// Start a new journal with a `D` directive for COMMODITY, such as `D $1000.00`, so hledger
+    /// writes it the way ptatemp does. Printed before any output, and written when --post or
+    /// --diff targets an empty or missing journal
+    #[arg(long, value_name = "COMMODITY")]
+    default_commodity: Option<String>,
+
+    /// Start a new journal with `decimal-mark .`, as --default-commodity does. hledger only;
+    /// ledger rejects the directive
+    #[arg(long)]
+    decimal_mark_directive: bool,
+
+    /// Start a new journal with LINE, such as `commodity 1.000,00 EUR`, as --default-commodity
+    /// does; may be repeated
+    #[arg(long, value_name = "LINE")]
+    directive: Vec<String>,
+
+    /// Make --post insert each transaction after the last one dated on or before it, rewriting
+    /// the journal, instead of appending to the end
+    #[arg(long, requires = "post")]
+    insert_sorted: bool,
+
+    /// Let --post create the journal, and --format FORMAT:FILE its file, if it does not exist yet
+    #[arg(long)]
+    create: bool,
+
+    /// Whether to end the printed transaction with a newline
+    #[arg(long, value_enum, default_value_t)]
+    trailing_newline: TrailingNewline,
+
+    /// Tag the transaction with an hledger `KEY:VALUE` comment; may be repeated
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tag: Vec<(String, String)>,
+
+    /// Tag the transaction with KEY and its value from the context; may be repeated
+    #[arg(long, value_name = "KEY")]
+    context_tag: Vec<String>,
+
+    /// Fail unless the template produces exactly N postings, or exactly the comma separated
+    /// ACCOUNTS (virtual ones in brackets); overrides `expect` in the template front matter
+    #[arg(long, value_name = "N|ACCOUNTS")]
+    expect: Option<String>,
+
+    /// Expand account code CODE to FULL in postings and placeholders; may be repeated
+    #[arg(long, value_name = "CODE=FULL", value_parser = parse_alias_arg)]
+    alias: Vec<(String, String)>,
+
+    /// JSON config file [default: $XDG_CONFIG_HOME/ptatemp/config.json]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Read balances from an `account<TAB>balance` file, asking hledger/ledger only for accounts
+    /// it lacks and for placeholders with modifiers
+    #[arg(long, value_name = "FILE")]
+    balance_cache: Option<PathBuf>,
 
+    /// Fail instead of asking hledger/ledger when --balance-cache cannot answer a placeholder
+    #[arg(long, requires = "balance_cache")]
+    strict: bool,
+
+    /// Read market prices for `in=` placeholder conversions from FILE, a journal of `P`
+    /// directives
+    #[arg(long, value_name = "FILE")]
+    price_db: Option<PathBuf>,
+
+    /// Let `in=` placeholder conversions use prices implied by transaction costs (hledger's
+    /// --infer-market-prices; ledger always does this)
+    #[arg(long)]
+    infer_market_prices: bool,
+
+    /// Find placeholder balances in --balance-cache whatever the case of the account, so
+    /// `<<assets:checking>>` matches `Assets:Checking`. hledger and ledger ignore case already
+    #[arg(long)]
+    fold_case_accounts: bool,
+
+    /// Write every account balance the template needed to FILE, in the --balance-cache format
+    #[arg(long, value_name = "FILE")]
+    dump_balances: Option<PathBuf>,
+
+    /// Print each hledger/ledger command used to resolve a balance placeholder to stderr
+    #[arg(long)]
+    show_command: bool,
+
+    /// Run hledger/ledger up to N more times, waiting 100ms and then twice as long each time,
+    /// when a balance lookup fails, such as on a journal locked by another process. A missing
+    /// hledger/ledger is not retried
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    balance_retries: u32,
+
+    /// Kill hledger/ledger and fail if a balance lookup runs longer than SECONDS. By default
+    /// there is no limit
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    balance_timeout: Option<u64>,
+
+    /// How errors are written to stderr
+    #[arg(long, value_enum, default_value_t, global = true)]
+    error_format: ErrorFormat,
+
+    /// Do not print errors to stderr; only the exit code reports failure
     #[arg(short, long)]
-    context: String,
+    quiet: bool,
 }
 
 impl Cli {
-    pub fn get_date(&self) -> chrono::DateTime<Local> {
-        let d = match &self.date {
-            None => return chrono::Local::now(),
-            Some(d) => d,
-        };
-        let nd = match chrono::NaiveDateTime::parse_from_str(&d, "%Y-%m-%d") {
-            Ok(d) => d,
-            Err(_) => return chrono::Local::now(),
-        };
-        match Local.from_local_datetime(&nd) {
-            chrono::offset::LocalResult::None => chrono::Local::now(),
-            chrono::offset::LocalResult::Single(a) => a,
-            chrono::offset::LocalResult::Ambiguous(a, _) => a,
+    /// Fill in `--template`, `--desc`, and `--date` from the positional `TEMPLATE DESC [DATE]`
+    /// form where the flags were not given.
+    fn merge_positionals(mut self) -> Self {
+        if let Some(path) = self.template_path.take() {
+            self.template.insert(0, path);
+        }
+        if self.desc.is_none() && self.payee.is_none() {
+            self.desc = self.desc_arg.take();
+        }
+        if self.date.is_none() {
+            self.date = self.date_arg.take();
+        }
+        self
+    }
+
+    /// Where named templates are looked up: `--template-dir`, then `PTATEMP_TEMPLATE_DIR`, then
+    /// `template_dir` in the config, then `templates` beside the default config file.
+    fn template_dir(&self, config: &Config) -> Option<PathBuf> {
+        self.template_dir
+            .clone()
+            .or_else(|| std::env::var_os("PTATEMP_TEMPLATE_DIR").map(PathBuf::from))
+            .or_else(|| config.template_dir.clone())
+            .or_else(|| Some(Config::default_path()?.with_file_name("templates")))
+    }
+
+    /// The `--template` arguments as paths. One that names no existing file but matches
+    /// `NAME.tmpl` in the template directory is that template; anything else is taken literally.
+    fn template_paths(&self, config: &Config) -> Vec<PathBuf> {
+        let dir = self.template_dir(config);
+        self.template
+            .iter()
+            .map(|arg| {
+                if arg.exists() {
+                    return arg.clone();
+                }
+                let mut name = arg.clone().into_os_string();
+                name.push(".tmpl");
+                match dir.as_ref().map(|dir| dir.join(&name)) {
+                    Some(named) if named.is_file() => named,
+                    _ => arg.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// The `--date` flag, falling back to `default` and then to now.
+    pub fn get_date(&self, default: Option<&str>) -> chrono::DateTime<Local> {
+        match self.date.as_deref().or(default) {
+            None => chrono::Local::now(),
+            Some(d) => local_date(d),
+        }
+    }
+
+    /// How to look up balances: the journal, aliases from `config` and `--alias`, and the balance
+    /// cache.
+    fn balance_options(&self, config: &Config) -> Result<BalanceOptions, LedgerError> {
+        let mut aliases = config.aliases.clone().separator(self.account_separator);
+        aliases.extend(self.alias.iter().cloned());
+        Ok(BalanceOptions {
+            journal: self.get_journal()?,
+            show_command: self.show_command,
+            decimal_mark: self.decimal_separator,
+            aliases,
+            normalize_accounts: self.normalize_accounts,
+            account_separator: self.account_separator,
+            cache: self
+                .balance_cache
+                .as_deref()
+                .map(BalanceCache::load)
+                .transpose()?,
+            strict: self.strict,
+            price_db: self.price_db.clone(),
+            infer_market_prices: self.infer_market_prices,
+            fold_case_accounts: self.fold_case_accounts,
+            retries: self.balance_retries,
+            timeout: self.balance_timeout.map(Duration::from_secs),
+            resolved: RefCell::default(),
+            on_event: Some(print_run_event),
+        })
+    }
+
+    /// The formats `--format` prints to stdout: those without a file, or ledger when `--format`
+    /// is not given at all.
+    fn stdout_formats(&self) -> Vec<OutputFormat> {
+        if self.format.is_empty() {
+            return vec![OutputFormat::Ledger];
         }
+        self.format
+            .iter()
+            .filter(|target| target.path.is_none())
+            .map(|target| target.format)
+            .collect()
     }
 
-    pub fn get_journal(&self) -> Result<PathBuf, std::env::VarError> {
+    /// `--journal`, then `LEDGER_FILE`, then `~/.hledger.journal` if it exists, as hledger does.
+    pub fn get_journal(&self) -> Result<PathBuf, LedgerError> {
         if let Some(j) = &self.journal {
             return Ok(j.to_path_buf());
         }
+        if let Some(j) = std::env::var_os("LEDGER_FILE").filter(|j| !j.is_empty()) {
+            return Ok(PathBuf::from(j));
+        }
+        std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".hledger.journal"))
+            .filter(|j| j.is_file())
+            .ok_or(LedgerError::NoJournal)
+    }
+}
 
-        Ok(PathBuf::from(std::env::var("LEDGER_FILE")?))
+/// Midnight local time on a `%Y-%m-%d` date, or now if `d` is not such a date.
+fn local_date(d: &str) -> chrono::DateTime<Local> {
+    let nd = match chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d") {
+        Ok(d) => d.and_time(chrono::NaiveTime::MIN),
+        Err(_) => return chrono::Local::now(),
+    };
+    match Local.from_local_datetime(&nd) {
+        chrono::offset::LocalResult::None => chrono::Local::now(),
+        chrono::offset::LocalResult::Single(a) => a,
+        chrono::offset::LocalResult::Ambiguous(a, _) => a,
     }
 }
 
-fn get_balance(account: &str, journal: &Path) -> Result<i64, LedgerError> {
-    let stdout = match std::process::Command::new("hledger")
-        .arg("-f")
-        .arg(journal.as_os_str())
-        .arg("bal")
-        .arg(account)
-        .output()
-    {
-        Ok(s) => s,
-        Err(_) => {
-            match std::process::Command::new("ledger")
-                .arg("-f")
-                .arg(journal.as_os_str())
-                .arg("bal")
-                .arg(account)
-                .output()
-            {
-                Ok(s) => s,
-                Err(e) => {
+/// Read a journal or template written in `encoding`.
+fn read_encoded(path: &Path, encoding: TextEncoding) -> Result<String, LedgerError> {
+    let bytes = std::fs::read(path)?;
+    match encoding.decode(&bytes) {
+        Ok(text) => Ok(text.into_owned()),
+        Err(e) => Err(LedgerError::Misc(format!(
+            "{} is not valid UTF-8 ({}); see --journal-encoding",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// Append `text` to a journal written in `encoding`, as one locked write.
+fn append_encoded(
+    path: &Path,
+    text: &str,
+    encoding: TextEncoding,
+    create: bool,
+) -> Result<(), LedgerError> {
+    let bytes = encoding.encode(text).map_err(|c| {
+        LedgerError::Misc(format!(
+            "{:?} cannot be written in the journal's encoding",
+            c
+        ))
+    })?;
+    match append_locked(path.to_path_buf(), &bytes, create) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(LedgerError::Misc(format!(
+            "Journal {} does not exist; pass --create to start a new one",
+            path.display()
+        ))),
+        result => Ok(result?),
+    }
+}
+
+/// Replace a journal with `text` written in `encoding`. The new contents go to a temporary file
+/// beside it that is then renamed over it, so the journal is never left half written.
+fn rewrite_encoded(path: &Path, text: &str, encoding: TextEncoding) -> Result<(), LedgerError> {
+    let bytes = encoding.encode(text).map_err(|c| {
+        LedgerError::Misc(format!(
+            "{:?} cannot be written in the journal's encoding",
+            c
+        ))
+    })?;
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".ptatemp-tmp");
+    let temporary = PathBuf::from(temporary);
+    std::fs::write(&temporary, &bytes)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+const EXAMPLE_TEMPLATE: &str = include_str!("example.tmpl");
+
+fn init_template(path: &Path) -> Result<(), LedgerError> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(EXAMPLE_TEMPLATE.as_bytes())?;
+    Ok(())
+}
+
+/// The postings without an amount, with the amounts that balance them filled in.
+fn balancing_postings(line_items: Vec<LineItem>) -> Result<Vec<LineItem>, LedgerError> {
+    let inferred = TransactionBuilder::new()
+        .line_items(line_items)
+        .infer_elided()?;
+    if inferred.is_empty() {
+        return Err(LedgerError::Misc(String::from(
+            "No posting leaves out its amount, so there is nothing to infer",
+        )));
+    }
+    Ok(inferred
+        .into_iter()
+        .map(|line| LineItem {
+            elided: false,
+            ..line
+        })
+        .collect())
+}
+
+fn preview_balance(line_items: Vec<LineItem>) -> Result<(), LedgerError> {
+    let inferred = balancing_postings(line_items)?;
+    for line in inferred {
+        let account = if line.is_real {
+            line.account.to_owned()
+        } else {
+            format!("[{}]", line.account)
+        };
+        println!(
+            "The balancing posting would be {} to {}",
+            line.amount(),
+            account
+        );
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<(), LedgerError> {
+    match &cli.command {
+        Some(Command::InitTemplate { path }) => return init_template(path),
+        Some(Command::Doctor) => return doctor::doctor(),
+        Some(Command::Balance { account, raw }) => {
+            let config = Config::load(cli.config.as_deref())?;
+            let report = balance_report(account, &cli.balance_options(&config)?)?;
+            match (raw, report.value) {
+                (true, _) => print!("{}", report.raw),
+                (false, Some(value)) => println!("{}", format_amount(value, "")),
+                (false, None) => {
                     return Err(LedgerError::Misc(format!(
-                        "Failed to execute hledger and ledger commands. Are they installed?: {}",
-                        e
+                        "The balance of {} is not a single amount; see --raw",
+                        account
                     )))
                 }
             }
+            return Ok(());
+        }
+        Some(Command::Reverse(args)) => {
+            return reverse::reverse(args, &cli.get_journal()?, cli.journal_encoding)
+        }
+        Some(Command::Price(args)) => {
+            let config = Config::load(cli.config.as_deref())?;
+            return price::price(
+                args,
+                &cli.get_journal()?,
+                cli.journal_encoding,
+                &config.commodity_styles,
+            );
         }
+        Some(
+            Command::OpeningBalances(_)
+            | Command::Lint { .. }
+            | Command::Import(_)
+            | Command::Recur(_),
+        )
+        | None => (),
     }
-    .stdout;
-    let mut split = stdout.split(|c| char::from(*c) == '\n');
-    let balance_bytes: Vec<u8> = match split.nth_back(1) {
-        Some(b) => b,
-        None => {
-            return Err(LedgerError::Misc(format!(
-                "Could not parse balance for account {}",
-                account
+    let config = Config::load(cli.config.as_deref())?;
+    let template_paths = cli.template_paths(&config);
+    if cli.validate_template {
+        for path in &template_paths {
+            template::validate(&read_encoded(path, cli.journal_encoding)?)
+                .map_err(|e| LedgerError::Misc(format!("{}: {}", path.display(), e)))?;
+            println!("{}: ok", path.display());
+        }
+        return Ok(());
+    }
+    let context = match &cli.context_env {
+        Some(var) => Some(std::env::var(var).map_err(|e| {
+            LedgerError::Misc(format!("Could not read --context-env {}: {}", var, e))
+        })?),
+        None => cli.context.clone(),
+    };
+    let balance_options = cli.balance_options(&config)?;
+    // opening-balances renders no template; its one transaction is written out like any other
+    let opening = match &cli.command {
+        Some(Command::OpeningBalances(args)) => {
+            Some(opening::opening_balances(args, &balance_options)?)
+        }
+        _ => None,
+    };
+    let contexts = match (cli.template.is_empty(), &cli.command, &context) {
+        (_, Some(Command::OpeningBalances(_)), _) => Vec::new(),
+        (false, Some(Command::Import(args)), _) => import::contexts(args, cli.journal_encoding)?,
+        (false, Some(Command::Recur(_)), _) if cli.date.is_some() => {
+            return Err(LedgerError::Misc(String::from(
+                "--date would override every occurrence of recur; use recur --start",
+            )))
+        }
+        (false, Some(Command::Recur(args)), context) => recur::contexts(args, context.as_deref())?,
+        (false, _, Some(context)) => {
+            // An array of contexts renders the template once per entry, producing one
+            // transaction each
+            match serde_json::from_str(context)? {
+                serde_json::Value::Array(entries) => entries,
+                context => vec![context],
+            }
+        }
+        _ => {
+            return Err(LedgerError::Misc(String::from(
+                "--template and --context are required",
             )))
         }
+    };
+    let mut templates = Vec::new();
+    let mut front_matter = FrontMatter::default();
+    for path in &template_paths {
+        let template =
+            Template::load(&read_encoded(path, cli.journal_encoding)?, &balance_options)?
+                .post_render_cmd(cli.post_render_cmd.clone());
+        front_matter.merge(&template.front_matter);
+        templates.push(template);
+    }
+    if let Some(path) = &cli.dump_balances {
+        BalanceCache::write(path, &balance_options.resolved.borrow())?;
+    }
+    let parse_options = ParseOptions::new()
+        .decimal_mark(cli.decimal_separator)
+        .unit(cli.input_amount_unit)
+        .max_decimal_places(cli.max_decimal_places);
+    let mut format = DisplayFormat::new()
+        .indent(cli.indent.clone())
+        .omit_commodity(cli.no_currency)
+        .commodity_styles(config.commodity_styles)
+        .show_unit_price(cli.show_unit_price);
+    if let Some(width) = cli.align_decimals {
+        format = format.decimal_align_width(width);
+    }
+    for (from, to) in &cli.commodity_map {
+        format = format.map_commodity(from, to);
+    }
+    let stdout_formats = cli.stdout_formats();
+    if cli.post && !cli.format.is_empty() && !stdout_formats.is_empty() {
+        return Err(LedgerError::Misc(String::from(
+            "--post writes the journal; give --format a FILE, as in json:audit.jsonl",
+        )));
     }
-    .iter()
-    .filter_map(|c| {
-        if char::from(*c).is_digit(10) || char::from(*c) == '-' || char::from(*c) == '.' {
-            Some(*c)
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    let mut to_post = Vec::new();
+    let mut accounts = BTreeSet::new();
+    let mut printed = 0;
+    let header = journal_header(&cli, &format);
+    let lint_json = match &cli.command {
+        Some(Command::Lint { json }) => Some(*json),
+        _ => None,
+    };
+    let mut lints = Vec::new();
+    if !(cli.post
+        || cli.diff
+        || cli.preview_balance
+        || cli.only_balancing
+        || lint_json.is_some()
+        || stdout_formats.is_empty())
+    {
+        write!(stdout, "{}", header)?;
+    }
+    let env = (!cli.expose_env.is_empty()).then(|| exposed_env(&cli.expose_env));
+    let balances = if cli.balance_accounts.is_empty() {
+        None
+    } else {
+        Some(balance_list(&cli.balance_accounts, &balance_options)?)
+    };
+    let source = match (cli.infer_signs, &cli.source) {
+        (true, Some(source)) => Some(balance_options.canonical_account(source)),
+        _ => None,
+    };
+    let is_batch = contexts.len() > 1;
+    for (entry, mut context) in contexts.into_iter().enumerate() {
+        let mut entry_date = context_string(&context, "date").or(front_matter.date.clone());
+        if cli.interactive && cli.date.is_none() && entry_date.is_none() {
+            entry_date = Some(prompt("Date [today]")?).filter(|d| !d.is_empty());
+        }
+        let date = cli.get_date(entry_date.as_deref());
+        let day = date.date_naive();
+        if cli.since.is_some_and(|since| day < since) || cli.until.is_some_and(|until| day >= until)
+        {
+            continue;
+        }
+        if cli.interactive {
+            ask_missing_variables(&templates, &mut context, env.is_some(), balances.is_some())?;
+        }
+        // Recorded before env and balances are added, since a rerun adds them again
+        let recorded_context = if cli.record_invocation {
+            Some(serde_json::to_string(&context)?)
         } else {
             None
+        };
+        let mut context = context;
+        if let (Some(env), Some(object)) = (&env, context.as_object_mut()) {
+            if !object.contains_key("env") {
+                object.insert(String::from("env"), env.clone());
+            }
         }
-    })
-    .collect();
-    let balance_str = String::from_utf8_lossy(&balance_bytes);
-    let balance_f64: f64 = balance_str.parse().or(Err(LedgerError::Misc(format!(
-        "Could not parse f64 for balance of account {}",
-        account
-    ))))?;
-    Ok((balance_f64 * 100.0).round() as i64)
-}
-
-fn render_balances(template_str: &str, journal: PathBuf) -> Result<String, LedgerError> {
-    let regex = Regex::new("<<.*>>").unwrap();
-    let accounts: Vec<&str> = regex
-        .find_iter(&template_str)
-        .filter_map(|m| {
-            let sub = m.as_str();
-            sub.get(2..sub.len() - 2)
+        if let (Some(balances), Some(object)) = (&balances, context.as_object_mut()) {
+            if !object.contains_key("balances") {
+                object.insert(String::from("balances"), balances.clone());
+            }
+        }
+        let tags = context_tags(&cli, &context)?;
+        if cli.print_context {
+            eprintln!("{}", serde_json::to_string_pretty(&context)?);
+        }
+        let entry_desc = context_string(&context, "desc");
+        let parse_options = match &cli.percent_base {
+            Some(key) => {
+                parse_options
+                    .clone()
+                    .percent_base(percent_base(&context, key, &parse_options)?)
+            }
+            None => parse_options.clone(),
+        };
+        let context = minijinja::Value::from_serialize(context);
+        if lint_json.is_some() {
+            let mut text = String::new();
+            for template in &templates {
+                text.push_str(&template.render_text(context.clone())?);
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+            lints.extend(
+                lint::lint(&text, &parse_options, &balance_options)
+                    .into_iter()
+                    .map(|lint| lint::Lint {
+                        entry: is_batch.then_some(entry),
+                        ..lint
+                    }),
+            );
+            continue;
+        }
+        let mut line_items = Vec::new();
+        for template in &templates {
+            line_items.extend(template.render(context.clone(), &parse_options)?);
+        }
+        if cli.drop_zero_postings {
+            line_items.retain(|l| l.elided || l.value != 0);
+        }
+        // Caught here because "needs at least two postings" hides that nothing rendered at all
+        if line_items.is_empty() {
+            return Err(LedgerError::Misc(String::from(
+                "Template produced no postings; check your context",
+            )));
+        }
+        if cli.require_all_real || cli.require_all_virtual {
+            require_posting_class(&line_items, cli.require_all_real)?;
+        }
+        for line in &mut line_items {
+            line.account = balance_options.canonical_account(&line.account);
+        }
+        if let Some(source) = &source {
+            infer_signs(&mut line_items, source)?;
+        }
+        if cli.emit_account_directives {
+            accounts.extend(line_items.into_iter().map(|l| l.account));
+            continue;
+        }
+        if cli.preview_balance {
+            preview_balance(line_items)?;
+            continue;
+        }
+        if cli.only_balancing {
+            for line in balancing_postings(line_items)? {
+                writeln!(stdout, "{}{}", format.indent, line.display_with(&format))?;
+            }
+            continue;
+        }
+        let desc = entry_desc.or(front_matter.desc.clone());
+        let builder = match (&cli.desc, &cli.payee, desc) {
+            (Some(desc), _, _) => TransactionBuilder::new().desc(desc),
+            (None, Some(payee), _) => {
+                TransactionBuilder::new().payee_note(payee, cli.note.as_ref())
+            }
+            (None, None, Some(desc)) => TransactionBuilder::new().desc(desc),
+            (None, None, None) if cli.interactive => {
+                TransactionBuilder::new().desc(prompt("Description")?)
+            }
+            (None, None, None) => TransactionBuilder::new(),
+        };
+        let mut builder = tags
+            .into_iter()
+            .fold(builder, |builder, (key, value)| builder.tag(key, value));
+        if let Some(expect) = cli.expect.as_ref().or(front_matter.expect.as_ref()) {
+            builder = builder.expect(Expectation::from(expect.as_str()));
+        }
+        let transaction = match builder
+            .date(date)
+            .balance_tolerance(cli.balance_tolerance)
+            .check_virtual_balance(!cli.no_virtual_balance_check)
+            .line_items(line_items)
+            .balance()
+        {
+            Err(TransactionBuilderError::DoesNotBalance(residual)) if source.is_some() => {
+                return Err(LedgerError::Misc(format!(
+                    "Transaction still does not balance by {} after inferring signs",
+                    residual
+                        .iter()
+                        .map(Amount::to_string)
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )))
+            }
+            result => result?,
+        };
+        if cli.summary {
+            eprintln!("{}", transaction.summary());
+        }
+        let transaction = if cli.idempotent {
+            let id = transaction.id();
+            transaction.with_tag("id", id.as_str())
+        } else {
+            transaction
+        };
+        let transaction = match &recorded_context {
+            Some(context) => {
+                let invocation = invocation(&template_paths, &transaction, context);
+                transaction.with_comment(invocation)
+            }
+            None => transaction,
+        };
+        emit_transaction(
+            &cli,
+            transaction,
+            &stdout_formats,
+            &format,
+            &mut stdout,
+            &mut printed,
+            &mut to_post,
+        )?;
+    }
+    if let Some(transaction) = opening {
+        emit_transaction(
+            &cli,
+            transaction,
+            &stdout_formats,
+            &format,
+            &mut stdout,
+            &mut printed,
+            &mut to_post,
+        )?;
+    }
+    if let Some(json) = lint_json {
+        return lint::report(&mut stdout, &lints, json);
+    }
+    if cli.emit_account_directives {
+        for account in accounts {
+            writeln!(stdout, "account {}", account)?;
+        }
+        stdout.flush()?;
+        return Ok(());
+    }
+    if cli.diff {
+        let journal = &balance_options.journal;
+        let existing = match read_encoded(journal, cli.journal_encoding) {
+            Err(LedgerError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                String::new()
+            }
+            result => result?,
+        };
+        diff::warn_duplicates(&existing, &to_post);
+        let mut appended = render_batch(&to_post, &format);
+        if existing.is_empty() {
+            appended.insert_str(0, &header);
+        }
+        write!(
+            stdout,
+            "{}",
+            diff::append_diff(journal, &existing, &appended)
+        )?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    if cli.post {
+        let journal = &balance_options.journal;
+        if cli.idempotent {
+            to_post = unposted(journal, to_post, cli.journal_encoding)?;
+            if to_post.is_empty() {
+                return Ok(());
+            }
+        }
+        if cli.show_post_bytes {
+            return show_post_bytes(&mut stdout, &cli, journal, &to_post, &format, &header);
+        }
+        let post = || {
+            post_to_journal(&cli, journal, &to_post, &format, &header)?;
+            write_output_targets(&cli, &to_post, &format)
+        };
+        #[cfg(feature = "sqlite")]
+        if let Some(database) = &cli.sqlite {
+            return sqlite::mirror(database, &to_post, post);
+        }
+        return post();
+    }
+    write_output_targets(&cli, &to_post, &format)?;
+    if !cli.preview_balance
+        && !cli.only_balancing
+        && !stdout_formats.is_empty()
+        && cli.trailing_newline.wanted()
+    {
+        writeln!(stdout)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Queue `transaction` for `--post` and `--diff`, or else write it to stdout in each of
+/// `formats`, after the `printed` ones before it, and queue it for any `--format` FILE.
+fn emit_transaction(
+    cli: &Cli,
+    transaction: Transaction,
+    formats: &[OutputFormat],
+    format: &DisplayFormat,
+    stdout: &mut impl Write,
+    printed: &mut usize,
+    to_post: &mut Vec<Transaction>,
+) -> Result<(), LedgerError> {
+    if cli.post || cli.diff {
+        to_post.push(transaction);
+        return Ok(());
+    }
+    for output in formats {
+        if *printed > 0 {
+            writeln!(stdout)?;
+            if !matches!(output, OutputFormat::Json) {
+                writeln!(stdout)?;
+            }
+        }
+        *printed += 1;
+        match output {
+            OutputFormat::Ledger => transaction.display_with(format).write_to(stdout)?,
+            OutputFormat::Beancount => transaction.display_beancount(format).write_to(stdout)?,
+            OutputFormat::Json => write!(stdout, "{}", transaction_json(&transaction, format))?,
+        }
+    }
+    if cli.format.iter().any(|target| target.path.is_some()) {
+        to_post.push(transaction);
+    }
+    Ok(())
+}
+
+/// Tell the user on stderr about a retried or, under `--show-command`, started hledger/ledger
+/// run.
+fn print_run_event(event: &RunEvent) {
+    eprintln!("{}", event);
+}
+
+/// Ask `question` on stderr and read a one-line answer from stdin, for `--interactive`.
+fn prompt(question: &str) -> Result<String, LedgerError> {
+    eprint!("{}: ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        return Err(LedgerError::Misc(format!(
+            "--interactive needs an answer for {:?}, but stdin is closed",
+            question
+        )));
+    }
+    Ok(answer.trim().to_string())
+}
+
+/// Prompt for every variable `templates` read that `context` does not set, and add the answers
+/// to it. An answer that is valid JSON, such as `12.50` or `true`, keeps its type; anything else
+/// is a string. `env` and `balances` are not asked for when they are about to be added anyway.
+fn ask_missing_variables(
+    templates: &[Template],
+    context: &mut serde_json::Value,
+    has_env: bool,
+    has_balances: bool,
+) -> Result<(), LedgerError> {
+    let mut missing = BTreeSet::new();
+    for template in templates {
+        missing.extend(template.missing_variables(context)?);
+    }
+    missing.retain(|name| !(has_env && name == "env" || has_balances && name == "balances"));
+    let Some(object) = context.as_object_mut() else {
+        return Ok(());
+    };
+    for name in missing {
+        let answer = prompt(&name)?;
+        let value = serde_json::from_str(&answer).unwrap_or(serde_json::Value::String(answer));
+        object.insert(name, value);
+    }
+    Ok(())
+}
+
+/// The command line that regenerates `transaction`, for `--record-invocation`: its templates,
+/// description, date, and `context` as compact JSON. Other flags are not recorded.
+fn invocation(templates: &[PathBuf], transaction: &Transaction, context: &str) -> String {
+    let mut args = vec![String::from("ptatemp")];
+    for template in templates {
+        args.push(String::from("--template"));
+        args.push(shell_quote(&template.to_string_lossy()));
+    }
+    args.extend([
+        String::from("--desc"),
+        shell_quote(transaction.desc()),
+        String::from("--date"),
+        transaction.date().format("%Y-%m-%d").to_string(),
+        String::from("--context"),
+        shell_quote(context),
+    ]);
+    args.join(" ")
+}
+
+/// `transaction` as a JSON object for `--format json`. Amounts are in hundredths, as in
+/// `--error-format json`.
+fn transaction_json(transaction: &Transaction, format: &DisplayFormat) -> serde_json::Value {
+    let amount = |value: i64, commodity: &str| {
+        json!({
+            "value": value,
+            "commodity": format.mapped_commodity(commodity),
+        })
+    };
+    let postings: Vec<serde_json::Value> = transaction
+        .into_iter()
+        .map(|line| {
+            json!({
+                "account": line.account,
+                "virtual": !line.is_real,
+                "amount": amount(line.value, &line.commodity),
+                "price": line.price.as_ref().map(|p| amount(p.value, &p.commodity)),
+                "price_is_total": line.price_is_total,
+                "comment": line.comment,
+            })
         })
         .collect();
-    let mut fixed_template = template_str.to_owned();
-    for acct in &accounts {
-        let balance = get_balance(acct, journal.as_path())?;
-        fixed_template = fixed_template.replace(&format!("<<{}>>", acct), &balance.to_string());
-    }
-    Ok(fixed_template)
-}
-
-fn render_tempate(
-    template_file: PathBuf,
-    journal: PathBuf,
-    ctx: minijinja::Value,
-) -> Result<Vec<LineItem>, LedgerError> {
-    let template_env = minijinja::Environment::new();
-    let template_str = std::fs::read_to_string(template_file)?;
-    let template_str = render_balances(&template_str, journal)?;
-    let render = template_env.render_str(&template_str, ctx)?;
-    let mut lines = Vec::new();
-    for line in render.lines() {
-        lines.push(line.try_into()?);
+    json!({
+        "date": transaction.date().format("%Y-%m-%d").to_string(),
+        "desc": transaction.desc(),
+        "tags": transaction
+            .tags()
+            .iter()
+            .map(|(key, value)| (key.clone(), json!(value)))
+            .collect::<serde_json::Map<_, _>>(),
+        "comments": transaction.comments(),
+        "postings": postings,
+    })
+}
+
+/// Append `transactions` to every `--format FORMAT:FILE` target. JSON is always written as
+/// UTF-8; the other formats use the journal's encoding.
+fn write_output_targets(
+    cli: &Cli,
+    transactions: &[Transaction],
+    format: &DisplayFormat,
+) -> Result<(), LedgerError> {
+    if transactions.is_empty() {
+        return Ok(());
     }
-    Ok(lines)
+    for target in &cli.format {
+        let Some(path) = &target.path else {
+            continue;
+        };
+        let (text, encoding) = match target.format {
+            OutputFormat::Ledger => (render_batch(transactions, format), cli.journal_encoding),
+            OutputFormat::Beancount => (
+                transactions
+                    .iter()
+                    .map(|t| format!("{}\n", t.display_beancount(format)))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+                cli.journal_encoding,
+            ),
+            OutputFormat::Json => (
+                transactions
+                    .iter()
+                    .map(|t| format!("{}\n", transaction_json(t, format)))
+                    .collect(),
+                TextEncoding::Utf8,
+            ),
+        };
+        append_encoded(path, &text, encoding, cli.create)?;
+    }
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
-    let journal = cli.get_journal()?;
-    let context: serde_json::Value = match serde_json::from_str(&cli.context) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Could not parse context because of {}", e);
-            return Err(e)?;
-        }
+/// The transactions of `to_post` whose `id` tag is not yet in `journal` nor earlier in
+/// `to_post`, for `--idempotent`. Skipped transactions are reported on stderr.
+fn unposted(
+    journal: &Path,
+    to_post: Vec<Transaction>,
+    encoding: TextEncoding,
+) -> Result<Vec<Transaction>, LedgerError> {
+    let existing = match read_encoded(journal, encoding) {
+        Err(LedgerError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        result => result?,
     };
-    let line_items = match render_tempate(
-        cli.template.as_path().to_path_buf(),
-        journal,
-        minijinja::Value::from_serialize(context),
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            eprintln!("Failed to parse template because of {:?}", e);
-            return Err(e)?;
+    let mut posted: HashSet<String> = transaction_ids(&existing)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    Ok(to_post
+        .into_iter()
+        .filter(|transaction| {
+            let id = transaction.id();
+            let is_new = posted.insert(id.clone());
+            if !is_new {
+                eprintln!(
+                    "Skipping {} {}: already posted as id:{}",
+                    transaction.date().format("%Y-%m-%d"),
+                    transaction.desc(),
+                    id
+                );
+            }
+            is_new
+        })
+        .collect())
+}
+
+/// What `--post` does to the journal.
+enum JournalWrite {
+    /// Add this text to the end
+    Append(String),
+    /// Replace the whole journal with this text, for `--insert-sorted`
+    Rewrite(String),
+}
+
+/// Work out how `--post` writes `to_post` into `journal`: appended or, under `--insert-sorted`,
+/// in date order. A journal that is empty or missing starts with `header`.
+fn journal_write(
+    cli: &Cli,
+    journal: &Path,
+    to_post: &[Transaction],
+    format: &DisplayFormat,
+    header: &str,
+) -> Result<JournalWrite, LedgerError> {
+    if cli.insert_sorted {
+        let mut contents = match read_encoded(journal, cli.journal_encoding) {
+            Err(LedgerError::IoError(e))
+                if cli.create && e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                String::new()
+            }
+            Err(LedgerError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(LedgerError::Misc(format!(
+                    "Journal {} does not exist; pass --create to start a new one",
+                    journal.display()
+                )))
+            }
+            result => result?,
+        };
+        // insert_sorted adds its own blank line after the directives
+        if contents.is_empty() && !header.is_empty() {
+            contents = format!("{}\n", header.trim_end());
+        }
+        for transaction in to_post {
+            let text = format!("{}\n", transaction.display_with(format));
+            contents = insert_sorted(&contents, &text, transaction.date().date_naive());
+        }
+        return Ok(JournalWrite::Rewrite(contents));
+    }
+    let mut text = render_batch(to_post, format);
+    if std::fs::metadata(journal).map_or(true, |m| m.len() == 0) {
+        text.insert_str(0, header);
+    }
+    Ok(JournalWrite::Append(text))
+}
+
+/// Write `to_post` into `journal` for `--post`; see `journal_write`.
+fn post_to_journal(
+    cli: &Cli,
+    journal: &Path,
+    to_post: &[Transaction],
+    format: &DisplayFormat,
+    header: &str,
+) -> Result<(), LedgerError> {
+    match journal_write(cli, journal, to_post, format, header)? {
+        JournalWrite::Append(text) => {
+            append_encoded(journal, &text, cli.journal_encoding, cli.create)
+        }
+        JournalWrite::Rewrite(contents) => {
+            rewrite_encoded(journal, &contents, cli.journal_encoding)
         }
+    }
+}
+
+/// Print what `post_to_journal` would write for `--show-post-bytes`, one line at a time as a
+/// quoted string so that tabs, trailing spaces, and newlines are visible.
+fn show_post_bytes<W: Write>(
+    w: &mut W,
+    cli: &Cli,
+    journal: &Path,
+    to_post: &[Transaction],
+    format: &DisplayFormat,
+    header: &str,
+) -> Result<(), LedgerError> {
+    let (action, text) = match journal_write(cli, journal, to_post, format, header)? {
+        JournalWrite::Append(text) => ("append to", text),
+        JournalWrite::Rewrite(contents) => ("rewrite", contents),
     };
-    let transaction = match TransactionBuilder::new()
-        .date(cli.get_date())
-        .desc(cli.desc)
-        .line_items(line_items)
-        .balance()
+    let bytes = cli
+        .journal_encoding
+        .encode(&text)
+        .map_or(text.len(), |b| b.len());
+    writeln!(
+        w,
+        "Would {} {} ({} bytes):",
+        action,
+        journal.display(),
+        bytes
+    )?;
+    for line in text.split_inclusive('\n') {
+        writeln!(w, "{:?}", line)?;
+    }
+    Ok(w.flush()?)
+}
+
+/// The directives a new journal starts with, from `--decimal-mark-directive`,
+/// `--default-commodity`, and `--directive`, followed by a blank line; empty if there are none.
+fn journal_header(cli: &Cli, format: &DisplayFormat) -> String {
+    let mut lines = Vec::new();
+    if cli.decimal_mark_directive {
+        lines.push(String::from("decimal-mark ."));
+    }
+    if let Some(commodity) = &cli.default_commodity {
+        lines.push(format!(
+            "D {}",
+            format.commodity_styles.format(100_000, commodity)
+        ));
+    }
+    lines.extend(cli.directive.iter().cloned());
+    if lines.is_empty() {
+        return String::new();
+    }
+    format!("{}\n\n", lines.join("\n"))
+}
+
+/// The environment variables named by `patterns`, as a JSON object. A pattern ending in `*`
+/// matches every variable starting with the rest of it.
+fn exposed_env(patterns: &[String]) -> serde_json::Value {
+    let matches = |name: &str| {
+        patterns.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == p,
+        })
+    };
+    std::env::vars()
+        .filter(|(name, _)| matches(name))
+        .map(|(name, value)| (name, serde_json::Value::String(value)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// The `balances` context list for `--balance-accounts`, in the order the accounts were given.
+fn balance_list(
+    accounts: &[String],
+    options: &BalanceOptions,
+) -> Result<serde_json::Value, LedgerError> {
+    let balances = accounts
+        .iter()
+        .map(|account| {
+            Ok(serde_json::json!({
+                "account": options.canonical_account(account),
+                "balance": account_balance(account, options)?,
+            }))
+        })
+        .collect::<Result<Vec<_>, LedgerError>>()?;
+    Ok(balances.into())
+}
+
+/// Make every real posting to `source` negative, leaving the rest as written, so amounts can all
+/// be typed as positive.
+fn infer_signs(line_items: &mut [LineItem], source: &str) -> Result<(), LedgerError> {
+    let mut found = false;
+    for line in line_items
+        .iter_mut()
+        .filter(|l| l.is_real && l.account == source)
     {
-        Ok(t) => t,
-        Err(e) => {
-            eprintln!("Could not build transaction because of {:?}", e);
-            return Err(e)?;
+        line.value = -line.value.abs();
+        found = true;
+    }
+    if !found {
+        return Err(LedgerError::Misc(format!(
+            "No real posting to the source account {}",
+            source
+        )));
+    }
+    Ok(())
+}
+
+/// Fail naming every posting whose `is_real` is not `real`, for `--require-all-real` and
+/// `--require-all-virtual`.
+fn require_posting_class(line_items: &[LineItem], real: bool) -> Result<(), LedgerError> {
+    let offending: Vec<&str> = line_items
+        .iter()
+        .filter(|l| l.is_real != real)
+        .map(|l| l.account.as_str())
+        .collect();
+    if offending.is_empty() {
+        return Ok(());
+    }
+    let (expected, found) = if real {
+        ("real", "virtual")
+    } else {
+        ("virtual", "real")
+    };
+    Err(LedgerError::Misc(format!(
+        "Expected only {} postings, but found {} postings to {}",
+        expected,
+        found,
+        offending.join(", ")
+    )))
+}
+
+/// The `--tag` pairs followed by one tag for each `--context-tag` key, read from `context`.
+fn context_tags(
+    cli: &Cli,
+    context: &serde_json::Value,
+) -> Result<Vec<(String, String)>, LedgerError> {
+    let mut tags = cli.tag.clone();
+    for key in &cli.context_tag {
+        let value = match context.get(key) {
+            Some(serde_json::Value::String(s)) => s.to_owned(),
+            Some(v @ (serde_json::Value::Number(_) | serde_json::Value::Bool(_))) => v.to_string(),
+            _ => {
+                return Err(LedgerError::Misc(format!(
+                    "Context key {} is missing or is not a string, number, or boolean",
+                    key
+                )))
+            }
+        };
+        if !is_valid_tag(key, &value) {
+            return Err(LedgerError::Misc(format!(
+                "Context key {} cannot be used as a tag",
+                key
+            )));
+        }
+        tags.push((key.to_owned(), value));
+    }
+    Ok(tags)
+}
+
+/// The amount in context key `key` that `%` amounts are taken against, for `--percent-base`. A
+/// number is read like an amount without a commodity.
+fn percent_base(
+    context: &serde_json::Value,
+    key: &str,
+    options: &ParseOptions,
+) -> Result<Amount, LedgerError> {
+    let raw = match context.get(key) {
+        Some(serde_json::Value::String(s)) => s.to_owned(),
+        Some(v @ serde_json::Value::Number(_)) => v.to_string(),
+        _ => {
+            return Err(LedgerError::Misc(format!(
+                "--percent-base needs the context to have a number or amount under {}",
+                key
+            )))
         }
     };
-    println!("{}", transaction);
-    Ok(())
+    Ok(parse_amount(&raw, options)?)
+}
+
+/// A string field of a context entry, such as the per-transaction `date` or `desc` of a batch.
+fn context_string(context: &serde_json::Value, key: &str) -> Option<String> {
+    context.get(key)?.as_str().map(String::from)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse().merge_positionals();
+    let quiet = cli.quiet;
+    let error_format = cli.error_format;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match (quiet, error_format) {
+                (true, _) => (),
+                (false, ErrorFormat::Human) => eprintln!("Error: {}", e),
+                (false, ErrorFormat::Json) => eprintln!("{}", e.to_json()),
+            }
+            e.exit_code()
+        }
+    }
 }
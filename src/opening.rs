@@ -0,0 +1,61 @@
+use clap::Args;
+use pta_template_engine::transaction::{LineItemBuilder, Transaction, TransactionBuilder};
+
+use crate::{local_date, LedgerError};
+use pta_template_engine::balance::{account_balance, BalanceOptions};
+
+#[derive(Args)]
+pub struct OpeningArgs {
+    /// Accounts whose balances to carry over
+    #[arg(long, value_delimiter = ',', required = true)]
+    accounts: Vec<String>,
+
+    /// Account that takes the offsetting amount
+    #[arg(long, default_value = "Equity:Opening Balances")]
+    equity_account: String,
+
+    /// Date of the transaction, `%Y-%m-%d`. Defaults to today
+    #[arg(short, long)]
+    date: Option<String>,
+}
+
+/// The transaction that moves the current balance of each of `args.accounts` into a new journal,
+/// offset against the equity account. Empty accounts are left out.
+pub fn opening_balances(
+    args: &OpeningArgs,
+    options: &BalanceOptions,
+) -> Result<Transaction, LedgerError> {
+    let mut builder = TransactionBuilder::new().desc("Opening Balances");
+    let mut opened = 0;
+    for account in &args.accounts {
+        let balance = account_balance(account, options)?;
+        // Accounts that are already empty have nothing to open
+        if balance == 0 {
+            continue;
+        }
+        builder = builder.try_add_line(
+            LineItemBuilder::new()
+                .account(options.canonical_account(account))
+                .value(balance)
+                .is_real(true),
+        )?;
+        opened += 1;
+    }
+    if opened == 0 {
+        return Err(LedgerError::Misc(String::from(
+            "Every account given to opening-balances is empty",
+        )));
+    }
+    let equity = -builder.current_real_balance();
+    let builder = builder.try_add_line(
+        LineItemBuilder::new()
+            .account(options.canonical_account(&args.equity_account))
+            .value(equity)
+            .is_real(true),
+    )?;
+    let date = match &args.date {
+        Some(d) => local_date(d),
+        None => chrono::Local::now(),
+    };
+    Ok(builder.date(date).balance()?)
+}
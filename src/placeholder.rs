@@ -0,0 +1,73 @@
+/// Replace every `<<...>>` placeholder in `text` with what `value` returns for the text between
+/// the brackets, walking `text` once. A placeholder must open and close on the same line;
+/// anything else is left as it is.
+pub fn substitute<F, E>(text: &str, mut value: F) -> Result<String, E>
+where
+    F: FnMut(&str) -> Result<String, E>,
+{
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("<<") {
+        let after = &rest[start + 2..];
+        let line_end = after.find('\n').unwrap_or(after.len());
+        let Some(end) = after[..line_end].find(">>") else {
+            // Not a placeholder; keep the text up to the end of the line and carry on after it
+            let keep = start + 2 + line_end;
+            out.push_str(&rest[..keep]);
+            rest = &rest[keep..];
+            continue;
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&value(&after[..end])?);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` with each placeholder replaced by its contents in braces.
+    fn braced(text: &str) -> String {
+        substitute(text, |inner| Ok::<_, ()>(format!("{{{}}}", inner))).unwrap()
+    }
+
+    #[test]
+    fn text_without_placeholders_is_unchanged() {
+        assert_eq!(braced(""), "");
+        assert_eq!(braced("Assets:Bank  $1.00\n"), "Assets:Bank  $1.00\n");
+        assert_eq!(braced("a > b >> c"), "a > b >> c");
+    }
+
+    #[test]
+    fn adjacent_placeholders_are_each_replaced() {
+        assert_eq!(braced("<<A>><<B>>"), "{A}{B}");
+        assert_eq!(braced("<<A|count>> <<B>>\n<<C>>"), "{A|count} {B}\n{C}");
+        assert_eq!(braced("<<>>"), "{}");
+    }
+
+    #[test]
+    fn an_unterminated_placeholder_is_left_as_it_is() {
+        assert_eq!(braced("<<Assets:Bank"), "<<Assets:Bank");
+        assert_eq!(braced("<<A\n>> <<B>>"), "<<A\n>> {B}");
+        assert_eq!(braced("<<A <<B>>"), "{A <<B}");
+        assert_eq!(braced("<<A>> <<"), "{A} <<");
+    }
+
+    #[test]
+    fn an_error_from_value_stops_the_walk() {
+        let mut seen = Vec::new();
+        let result = substitute("<<A>> <<B>> <<C>>", |inner| {
+            seen.push(inner.to_string());
+            if inner == "B" {
+                Err("no B")
+            } else {
+                Ok(String::new())
+            }
+        });
+        assert_eq!(result, Err("no B"));
+        assert_eq!(seen, ["A", "B"]);
+    }
+}
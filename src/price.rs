@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use clap::Args;
+use pta_template_engine::amount::{parse_amount, ParseOptions};
+use pta_template_engine::commodity::CommodityStyles;
+use pta_template_engine::encoding::TextEncoding;
+
+use crate::{append_encoded, local_date, LedgerError};
+
+#[derive(Args)]
+pub struct PriceArgs {
+    /// Commodity being priced, such as `AAPL`
+    commodity: String,
+
+    /// Price of one unit to the cent, such as `$150.00`
+    price: String,
+
+    /// Date of the price, `%Y-%m-%d`. Defaults to today
+    #[arg(short, long)]
+    date: Option<String>,
+
+    /// Append the directive to the journal instead of printing it
+    #[arg(long)]
+    post: bool,
+
+    /// Let --post create the journal if it does not exist yet
+    #[arg(long, requires = "post")]
+    create: bool,
+}
+
+/// Print, or post, a `P DATE COMMODITY PRICE` market price directive.
+pub fn price(
+    args: &PriceArgs,
+    journal: &Path,
+    encoding: TextEncoding,
+    styles: &CommodityStyles,
+) -> Result<(), LedgerError> {
+    let commodity = args.commodity.trim();
+    if commodity.is_empty() || commodity.contains(char::is_whitespace) {
+        return Err(LedgerError::Misc(format!(
+            "Commodity {:?} must be a single word",
+            args.commodity
+        )));
+    }
+    let price = parse_amount(&args.price, &ParseOptions::new())
+        .map_err(|e| LedgerError::Misc(format!("could not parse price: {}", e)))?;
+    let date = match &args.date {
+        Some(d) => local_date(d),
+        None => chrono::Local::now(),
+    };
+    let directive = format!(
+        "P {} {} {}",
+        date.format("%Y-%m-%d"),
+        commodity,
+        styles.format(price.value, &price.commodity)
+    );
+    if args.post {
+        append_encoded(journal, &format!("{}\n", directive), encoding, args.create)?;
+    } else {
+        println!("{}", directive);
+    }
+    Ok(())
+}
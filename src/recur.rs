@@ -0,0 +1,151 @@
+use chrono::{Days, Months, NaiveDate};
+use clap::{Args, ValueEnum};
+
+use crate::LedgerError;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Args)]
+pub struct RecurArgs {
+    /// How far apart the occurrences are
+    #[arg(long, value_name = "FREQUENCY")]
+    every: Frequency,
+
+    /// Number of occurrences to generate
+    #[arg(long, value_name = "N")]
+    count: u32,
+
+    /// Date of the first occurrence, `%Y-%m-%d`. Defaults to today
+    #[arg(long, value_name = "DATE")]
+    start: Option<String>,
+}
+
+/// The `n`th occurrence after `start`. Months and years are counted from `start` itself rather
+/// than from the previous occurrence, so a schedule starting on the 31st falls on the last day of
+/// shorter months and returns to the 31st afterwards.
+fn occurrence(start: NaiveDate, every: Frequency, n: u32) -> Option<NaiveDate> {
+    match every {
+        Frequency::Daily => start.checked_add_days(Days::new(n.into())),
+        Frequency::Weekly => start.checked_add_days(Days::new(u64::from(n) * 7)),
+        Frequency::Monthly => start.checked_add_months(Months::new(n)),
+        Frequency::Yearly => start.checked_add_months(Months::new(n.checked_mul(12)?)),
+    }
+}
+
+/// One template context per occurrence: `base`, or an empty object, with `date` and
+/// `occurrence_date` set to the occurrence's date.
+pub fn contexts(
+    args: &RecurArgs,
+    base: Option<&str>,
+) -> Result<Vec<serde_json::Value>, LedgerError> {
+    let base = match base {
+        Some(base) => match serde_json::from_str(base)? {
+            serde_json::Value::Object(base) => base,
+            _ => {
+                return Err(LedgerError::Misc(String::from(
+                    "recur needs --context to be a single JSON object",
+                )))
+            }
+        },
+        None => serde_json::Map::new(),
+    };
+    let start = match &args.start {
+        Some(start) => NaiveDate::parse_from_str(start, "%Y-%m-%d").map_err(|e| {
+            LedgerError::Misc(format!("Could not parse --start {:?}: {}", start, e))
+        })?,
+        None => chrono::Local::now().date_naive(),
+    };
+    (0..args.count)
+        .map(|n| {
+            let date = occurrence(start, args.every, n).ok_or_else(|| {
+                LedgerError::Misc(format!(
+                    "Occurrence {} of the schedule is out of range",
+                    n + 1
+                ))
+            })?;
+            let date = date.format("%Y-%m-%d").to_string();
+            let mut context = base.clone();
+            context.insert(String::from("date"), date.clone().into());
+            context.insert(String::from("occurrence_date"), date.into());
+            Ok(context.into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dates(start: &str, every: Frequency, count: u32) -> Vec<String> {
+        let args = RecurArgs {
+            every,
+            count,
+            start: Some(start.to_string()),
+        };
+        contexts(&args, Some(r#"{"amount": 1200}"#))
+            .unwrap()
+            .iter()
+            .map(|context| {
+                assert_eq!(context["amount"], 1200);
+                assert_eq!(context["date"], context["occurrence_date"]);
+                context["date"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn monthly_from_the_31st_clamps_and_returns_to_the_31st() {
+        assert_eq!(
+            dates("2024-01-31", Frequency::Monthly, 5),
+            [
+                "2024-01-31",
+                "2024-02-29",
+                "2024-03-31",
+                "2024-04-30",
+                "2024-05-31"
+            ]
+        );
+        assert_eq!(
+            dates("2023-01-31", Frequency::Monthly, 3),
+            ["2023-01-31", "2023-02-28", "2023-03-31"]
+        );
+    }
+
+    #[test]
+    fn yearly_from_a_leap_day_falls_on_the_28th_in_other_years() {
+        assert_eq!(
+            dates("2024-02-29", Frequency::Yearly, 5),
+            [
+                "2024-02-29",
+                "2025-02-28",
+                "2026-02-28",
+                "2027-02-28",
+                "2028-02-29"
+            ]
+        );
+    }
+
+    #[test]
+    fn count_zero_and_one() {
+        assert!(dates("2024-01-31", Frequency::Monthly, 0).is_empty());
+        assert_eq!(dates("2024-01-31", Frequency::Weekly, 1), ["2024-01-31"]);
+    }
+
+    #[test]
+    fn daily_and_weekly_cross_month_ends() {
+        assert_eq!(
+            dates("2024-02-28", Frequency::Daily, 3),
+            ["2024-02-28", "2024-02-29", "2024-03-01"]
+        );
+        assert_eq!(
+            dates("2024-12-25", Frequency::Weekly, 2),
+            ["2024-12-25", "2025-01-01"]
+        );
+    }
+}
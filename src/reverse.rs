@@ -0,0 +1,110 @@
+use std::path::Path;
+
+use clap::Args;
+use pta_template_engine::encoding::TextEncoding;
+use pta_template_engine::journal::entries;
+use pta_template_engine::transaction::Transaction;
+
+use crate::{append_encoded, local_date, read_encoded, LedgerError};
+
+#[derive(Args)]
+pub struct ReverseArgs {
+    /// Reverse the transaction that spans this line of the journal
+    #[arg(long, value_name = "N", conflicts_with_all = ["match_date", "match_desc"])]
+    line: Option<usize>,
+
+    /// Reverse the transaction on this date (with --match-desc)
+    #[arg(long, value_name = "DATE", requires = "match_desc")]
+    match_date: Option<String>,
+
+    /// Reverse the transaction with exactly this description
+    #[arg(long, value_name = "DESC", required_unless_present = "line")]
+    match_desc: Option<String>,
+
+    /// Date of the reversal, `%Y-%m-%d`. Defaults to today
+    #[arg(short, long)]
+    date: Option<String>,
+
+    /// Append the reversal to the journal instead of printing it
+    #[arg(long)]
+    post: bool,
+
+    /// Leave out the original's comments, both `;` lines and trailing posting comments, which
+    /// are otherwise carried over to the reversal. Tags are never carried over
+    #[arg(long)]
+    strip_comments: bool,
+}
+
+pub fn reverse(
+    args: &ReverseArgs,
+    journal: &Path,
+    encoding: TextEncoding,
+) -> Result<(), LedgerError> {
+    let contents = read_encoded(journal, encoding)?;
+    let entries = entries(&contents);
+    let mut matches = Vec::new();
+    for entry in &entries {
+        let header = entry.text.lines().next().unwrap_or_default();
+        let selected = match (args.line, &args.match_date) {
+            (Some(line), _) => (entry.first_line..=entry.last_line).contains(&line),
+            (None, Some(date)) => header.starts_with(date.as_str()),
+            (None, None) => true,
+        };
+        if !selected {
+            continue;
+        }
+        let transaction: Transaction = match entry.text.parse() {
+            Ok(t) => t,
+            // When searching by description, entries this parser cannot read are passed over
+            Err(_) if args.line.is_none() => continue,
+            Err(e) => {
+                return Err(LedgerError::Misc(format!(
+                    "Could not read the transaction at line {} of {}: {}",
+                    entry.first_line,
+                    journal.display(),
+                    e
+                )))
+            }
+        };
+        if args
+            .match_desc
+            .as_ref()
+            .is_none_or(|desc| transaction.desc() == desc)
+        {
+            matches.push((entry.first_line, transaction));
+        }
+    }
+    let transaction = match matches.as_slice() {
+        [(_, transaction)] => transaction,
+        [] => {
+            return Err(LedgerError::Misc(format!(
+                "No transaction in {} matches",
+                journal.display()
+            )))
+        }
+        _ => {
+            return Err(LedgerError::Misc(format!(
+                "Several transactions match, starting at lines {}; use --line to pick one",
+                matches
+                    .iter()
+                    .map(|(line, _)| line.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )))
+        }
+    };
+    let date = match &args.date {
+        Some(d) => local_date(d),
+        None => chrono::Local::now(),
+    };
+    let mut reversal = transaction.reversal().date(date).balance()?;
+    if args.strip_comments {
+        reversal = reversal.without_comments();
+    }
+    if args.post {
+        append_encoded(journal, &format!("{}\n", reversal), encoding, false)?;
+    } else {
+        println!("{}", reversal);
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use pta_template_engine::transaction::Transaction;
+
+use crate::LedgerError;
+
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS transactions (
+    id INTEGER PRIMARY KEY,
+    date TEXT NOT NULL,
+    description TEXT NOT NULL,
+    content_hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS postings (
+    id INTEGER PRIMARY KEY,
+    transaction_id INTEGER NOT NULL REFERENCES transactions(id),
+    account TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    commodity TEXT NOT NULL,
+    is_real INTEGER NOT NULL
+);
+";
+
+/// Written after the inserts; reading it back means they all succeeded.
+const READY: &str = "ptatemp-ready";
+
+fn quoted(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// SQL inserting `transaction` and its postings. Amounts are stored in cents.
+fn insert(transaction: &Transaction) -> String {
+    let mut sql = format!(
+        "INSERT INTO transactions (date, description, content_hash) VALUES ({}, {}, {});\n",
+        quoted(&transaction.date().format("%Y-%m-%d").to_string()),
+        quoted(transaction.desc()),
+        quoted(&format!("{:016x}", transaction.content_hash()))
+    );
+    for posting in transaction {
+        sql.push_str(&format!(
+            "INSERT INTO postings (transaction_id, account, amount, commodity, is_real) \
+             VALUES ((SELECT max(id) FROM transactions), {}, {}, {}, {});\n",
+            quoted(&posting.account),
+            posting.value,
+            quoted(&posting.commodity),
+            i64::from(posting.is_real)
+        ));
+    }
+    sql
+}
+
+/// Insert `transactions` into the SQLite database at `path`, creating its schema if needed, and
+/// run `post` while the database transaction is open. The rows are committed only if `post`
+/// succeeds, so the database and the journal do not drift apart.
+///
+/// This drives the `sqlite3` command line shell rather than linking SQLite, so `sqlite3` must be
+/// installed. Its error output is captured, so a failure names the statement sqlite3 stopped at.
+pub fn mirror<F>(path: &Path, transactions: &[Transaction], post: F) -> Result<(), LedgerError>
+where
+    F: FnOnce() -> Result<(), LedgerError>,
+{
+    let mut child = Command::new("sqlite3")
+        .arg("-bail")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| LedgerError::Misc(format!("Failed to run sqlite3 for --sqlite: {}", e)))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    // Read on its own thread so that sqlite3 cannot stall on a full pipe
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let stderr = std::thread::spawn(move || {
+        let mut text = String::new();
+        stderr_pipe.read_to_string(&mut text).map(|_| text)
+    });
+    let errors = |stderr: std::thread::JoinHandle<std::io::Result<String>>| {
+        let text = stderr.join().expect("stderr reader does not panic")?;
+        Ok::<String, LedgerError>(match text.trim() {
+            "" => String::new(),
+            text => format!(": {}", text),
+        })
+    };
+    let mut script = format!("BEGIN;\n{}", SCHEMA);
+    for transaction in transactions {
+        script.push_str(&insert(transaction));
+    }
+    script.push_str(&format!("SELECT '{}';\n", READY));
+    // A failed statement makes sqlite3 exit, which this write or the read below notices
+    let written = stdin
+        .write_all(script.as_bytes())
+        .and_then(|_| stdin.flush());
+    let mut line = String::new();
+    stdout.read_line(&mut line)?;
+    if written.is_err() || line.trim() != READY {
+        drop(stdin);
+        let status = child.wait()?;
+        return Err(LedgerError::Misc(format!(
+            "Could not insert into {} ({}){}; nothing was posted",
+            path.display(),
+            status,
+            errors(stderr)?
+        )));
+    }
+    let posted = post();
+    let end = if posted.is_ok() {
+        "COMMIT;\n"
+    } else {
+        "ROLLBACK;\n"
+    };
+    stdin.write_all(end.as_bytes())?;
+    drop(stdin);
+    let status = child.wait()?;
+    let errors = errors(stderr)?;
+    posted?;
+    if !status.success() {
+        return Err(LedgerError::Misc(format!(
+            "The journal was posted, but committing to {} failed with {}{}",
+            path.display(),
+            status,
+            errors
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::Local;
+    use pta_template_engine::transaction::LineItem;
+
+    use super::*;
+
+    fn database(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ptatemp-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn transaction() -> Transaction {
+        let postings = ["Expenses:Food  $4.50", "Assets:Cash  $-4.50"];
+        Transaction::new_unchecked(
+            Local::now(),
+            "Cafe",
+            postings
+                .iter()
+                .map(|p| p.parse::<LineItem>().unwrap())
+                .collect(),
+        )
+    }
+
+    fn query(path: &Path, sql: &str) -> String {
+        let output = Command::new("sqlite3").arg(path).arg(sql).output().unwrap();
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn commits_only_when_posting_succeeds() {
+        let path = database("commit");
+        mirror(&path, &[transaction()], || Ok(())).unwrap();
+        let failed = mirror(&path, &[transaction()], || {
+            Err(LedgerError::Misc(String::from("journal is locked")))
+        });
+        assert!(failed.is_err());
+        assert_eq!(query(&path, "SELECT count(*) FROM transactions"), "1");
+        assert_eq!(query(&path, "SELECT count(*) FROM postings"), "2");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_failed_insert_reports_sqlite3s_error_and_does_not_post() {
+        let path = database("insert");
+        query(&path, "CREATE TABLE transactions (id INTEGER PRIMARY KEY);");
+        let mut posted = false;
+        let error = mirror(&path, &[transaction()], || {
+            posted = true;
+            Ok(())
+        })
+        .unwrap_err()
+        .to_string();
+        assert!(!posted);
+        assert!(error.contains("no column named date"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
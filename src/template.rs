@@ -0,0 +1,257 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use minijinja::value::ValueKind;
+use pta_template_engine::amount::ParseOptions;
+use pta_template_engine::transaction::{split_amount, LineItem};
+
+use crate::LedgerError;
+use pta_template_engine::balance::{check_placeholders, render_balances, BalanceOptions};
+
+/// Transaction fields set in a `---` delimited block at the very top of a template:
+///
+/// ```text
+/// ---
+/// date: 2024-01-01
+/// desc: Rent
+/// ---
+/// ```
+///
+/// Only flat `key: value` pairs are understood. Command line flags take precedence.
+#[derive(Debug, Default)]
+pub struct FrontMatter {
+    pub date: Option<String>,
+    pub desc: Option<String>,
+    /// See `--expect`
+    pub expect: Option<String>,
+}
+
+impl FrontMatter {
+    /// Fill in any key not set here from `other`.
+    pub fn merge(&mut self, other: &FrontMatter) {
+        self.date = self.date.take().or(other.date.clone());
+        self.desc = self.desc.take().or(other.desc.clone());
+        self.expect = self.expect.take().or(other.expect.clone());
+    }
+
+    /// Separate the front matter, if any, from the body of the template.
+    pub fn split(template_str: &str) -> Result<(Self, &str), LedgerError> {
+        let Some(rest) = template_str
+            .strip_prefix("---\n")
+            .or_else(|| template_str.strip_prefix("---\r\n"))
+        else {
+            return Ok((Self::default(), template_str));
+        };
+        let mut front_matter = Self::default();
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            offset += line.len();
+            let line = line.trim();
+            if line == "---" {
+                return Ok((front_matter, &rest[offset..]));
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let value = |v: &str| {
+                let v = v.trim();
+                let unquoted = v
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .or_else(|| v.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')));
+                unquoted.unwrap_or(v).to_string()
+            };
+            match line.split_once(':') {
+                Some(("date", d)) => front_matter.date = Some(value(d)),
+                Some(("desc", d)) => front_matter.desc = Some(value(d)),
+                Some(("expect", e)) => front_matter.expect = Some(value(e)),
+                _ => {
+                    return Err(LedgerError::Misc(format!(
+                        "Unsupported front matter line: {}",
+                        line
+                    )))
+                }
+            }
+        }
+        Err(LedgerError::Misc(String::from(
+            "Template front matter is missing its closing ---",
+        )))
+    }
+}
+
+#[derive(Debug)]
+/// A template with its front matter split off and its balance placeholders filled in, ready to be
+/// rendered against any number of contexts.
+pub struct Template {
+    pub front_matter: FrontMatter,
+    body: String,
+    post_render_cmd: Option<String>,
+}
+
+fn run_post_render_cmd(cmd: &str, input: &str) -> Result<String, LedgerError> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Write from another thread so a command that prints before reading all its input cannot
+    // deadlock against us
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    let written = writer.join().expect("writer thread panicked");
+    if !output.status.success() {
+        return Err(LedgerError::Misc(format!(
+            "Post-render command `{}` failed with {}",
+            cmd, output.status
+        )));
+    }
+    // A command is free to stop reading early, as `head` does
+    match written {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => (),
+    }
+    String::from_utf8(output.stdout).map_err(|_| {
+        LedgerError::Misc(format!(
+            "Post-render command `{}` printed invalid UTF-8",
+            cmd
+        ))
+    })
+}
+
+fn split_amount_filter(
+    total: i64,
+    weights: minijinja::Value,
+) -> Result<minijinja::Value, minijinja::Error> {
+    let keys: Vec<minijinja::Value> = match weights.kind() {
+        ValueKind::Map => weights.try_iter()?.collect(),
+        _ => Vec::new(),
+    };
+    let weight_values: Vec<minijinja::Value> = if keys.is_empty() {
+        weights.try_iter()?.collect()
+    } else {
+        keys.iter()
+            .map(|k| weights.get_item(k))
+            .collect::<Result<_, _>>()?
+    };
+    let weight_floats = weight_values
+        .into_iter()
+        .map(f64::try_from)
+        .collect::<Result<Vec<f64>, _>>()?;
+    let parts = split_amount(total, &weight_floats).ok_or(minijinja::Error::new(
+        minijinja::ErrorKind::InvalidOperation,
+        "split_amount needs at least one non-negative weight and a non-zero weight sum",
+    ))?;
+    if keys.is_empty() {
+        Ok(minijinja::Value::from(parts))
+    } else {
+        Ok(keys.into_iter().zip(parts).collect())
+    }
+}
+
+/// Check a template's front matter, balance placeholders, and minijinja syntax without a
+/// journal or context. Errors name the line of the template they were found on.
+pub fn validate(template_str: &str) -> Result<(), LedgerError> {
+    let (_, body) = FrontMatter::split(template_str)?;
+    let first_line = template_str[..template_str.len() - body.len()]
+        .lines()
+        .count()
+        + 1;
+    check_placeholders(body, first_line)?;
+    let env = minijinja::Environment::new();
+    env.template_from_str(body).map_err(|e| {
+        let detail = e.detail().map(String::from).unwrap_or(e.kind().to_string());
+        match e.line() {
+            Some(line) => LedgerError::Misc(format!("line {}: {}", first_line + line - 1, detail)),
+            None => LedgerError::Misc(detail),
+        }
+    })?;
+    Ok(())
+}
+
+/// The minijinja environment templates are rendered in, with this crate's filters added.
+fn environment() -> minijinja::Environment<'static> {
+    let mut template_env = minijinja::Environment::new();
+    template_env.add_filter("split_amount", split_amount_filter);
+    template_env
+}
+
+impl Template {
+    /// Split off a template's front matter and resolve its `<<Account>>` placeholders. Balances
+    /// are looked up once here, not per render.
+    pub fn load(template_str: &str, balance_options: &BalanceOptions) -> Result<Self, LedgerError> {
+        let (front_matter, body) = FrontMatter::split(template_str)?;
+        let body = render_balances(body, balance_options)?;
+        Ok(Self {
+            front_matter,
+            body,
+            post_render_cmd: None,
+        })
+    }
+
+    /// Pipe every rendering through `cmd`, run by `sh -c`, and parse its stdout instead. See
+    /// `render` for where this happens.
+    pub fn post_render_cmd(self, cmd: Option<String>) -> Self {
+        Self {
+            post_render_cmd: cmd,
+            ..self
+        }
+    }
+
+    /// The text of the template rendered against `ctx` and passed through the post-render
+    /// command, before any of it is parsed.
+    pub fn render_text(&self, ctx: minijinja::Value) -> Result<String, LedgerError> {
+        let render = environment().render_str(&self.body, ctx)?;
+        match &self.post_render_cmd {
+            Some(cmd) => run_post_render_cmd(cmd, &render),
+            None => Ok(render),
+        }
+    }
+
+    /// Top-level variables the template reads that neither `ctx` nor minijinja's built-in globals
+    /// define, such as `amount` in `{{ amount }}`.
+    pub fn missing_variables(
+        &self,
+        ctx: &serde_json::Value,
+    ) -> Result<BTreeSet<String>, LedgerError> {
+        let template_env = environment();
+        let template = template_env.template_from_str(&self.body)?;
+        let state = template_env.empty_state();
+        Ok(template
+            .undeclared_variables(false)
+            .into_iter()
+            .filter(|name| ctx.get(name).is_none() && state.lookup(name).is_none())
+            .collect())
+    }
+
+    /// Render the template against `ctx` and parse one posting from each non-blank line.
+    ///
+    /// The text goes through these stages in order: balance placeholders were filled in by
+    /// `load`, then minijinja renders it, then the post-render command (if any) rewrites it, and
+    /// finally it is parsed. Whatever the command prints must therefore still be one
+    /// `ACCOUNT  AMOUNT` posting per line.
+    pub fn render(
+        &self,
+        ctx: minijinja::Value,
+        options: &ParseOptions,
+    ) -> Result<Vec<LineItem>, LedgerError> {
+        let render = self.render_text(ctx)?;
+        let mut lines = Vec::new();
+        for (i, line) in render.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line_item =
+                LineItem::parse_with(line, options).map_err(|cause| LedgerError::ParseLine {
+                    line_no: i + 1,
+                    content: line.to_string(),
+                    cause,
+                })?;
+            lines.push(line_item);
+        }
+        Ok(lines)
+    }
+}
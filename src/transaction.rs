@@ -1,18 +1,45 @@
-use std::{cmp::Ordering, fmt::Display, fs::OpenOptions, io::Write, path::PathBuf};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    str::FromStr,
+};
+#[cfg(feature = "native")]
+use std::{fs::OpenOptions, path::PathBuf};
 
 use chrono::Local;
 
+use crate::amount::{parse_amount, Amount, AmountUnit, ParseOptions, DEFAULT_COMMODITY};
+use crate::format::DisplayFormat;
+
 #[derive(Debug)]
 pub struct LineItem {
     pub account: String,
     pub value: i64,
+    pub commodity: String,
+    /// Per-unit cost written as `@ PRICE`, or with `price_is_total` the total cost written as
+    /// `@@ TOTAL`. When set, the posting balances in the price's commodity instead of its own.
+    pub price: Option<Amount>,
+    /// `price` is the cost of the whole posting rather than of one unit; see `unit_price`
+    pub price_is_total: bool,
     pub is_real: bool,
+    /// The amount is left off when displayed so ledger infers it. A posting parsed without an
+    /// amount is elided, and `TransactionBuilder::balance` fills in its `value`.
+    pub elided: bool,
+    /// Text of a trailing `; comment`, written after the amount. Ignored when comparing postings.
+    pub comment: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct LineItemBuilder {
     account: Option<String>,
     value: Option<i64>,
+    commodity: Option<String>,
+    price: Option<Amount>,
+    price_is_total: bool,
     is_real: Option<bool>,
 }
 
@@ -21,13 +48,36 @@ pub enum LineItemBuilderError {
     MissingAccount,
     MissingValue,
     MissingIsReal,
+    InvalidUtf8,
+    ParseValue(String),
+    /// A percentage amount was given without `ParseOptions::percent_base`
+    MissingPercentBase,
 }
 
 impl std::error::Error for LineItemBuilderError {}
 
 impl std::fmt::Display for LineItemBuilderError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::MissingAccount => write!(f, "posting is missing an account"),
+            Self::MissingValue => write!(f, "posting is missing an amount"),
+            Self::MissingIsReal => write!(
+                f,
+                "could not tell if the posting is real or virtual; wrap virtual accounts in [ and ]"
+            ),
+            Self::InvalidUtf8 => write!(f, "posting is not valid UTF-8"),
+            Self::ParseValue(raw) => write!(f, "could not parse amount {}", raw),
+            Self::MissingPercentBase => write!(
+                f,
+                "percentage amounts need a base to take the percentage of; see --percent-base"
+            ),
+        }
+    }
+}
+
+impl Default for LineItemBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -36,6 +86,9 @@ impl LineItemBuilder {
         Self {
             account: None,
             value: None,
+            commodity: None,
+            price: None,
+            price_is_total: false,
             is_real: None,
         }
     }
@@ -57,6 +110,33 @@ impl LineItemBuilder {
         }
     }
 
+    pub fn commodity<S>(self, commodity: S) -> Self
+    where
+        S: ToString,
+    {
+        Self {
+            commodity: Some(commodity.to_string()),
+            ..self
+        }
+    }
+
+    pub fn price(self, price: Amount) -> Self {
+        Self {
+            price: Some(price),
+            price_is_total: false,
+            ..self
+        }
+    }
+
+    /// Set the cost of the whole posting, written `@@ TOTAL`, instead of a per-unit `price`.
+    pub fn total_price(self, total: Amount) -> Self {
+        Self {
+            price: Some(total),
+            price_is_total: true,
+            ..self
+        }
+    }
+
     pub fn is_real(self, is_real: bool) -> Self {
         Self {
             is_real: Some(is_real),
@@ -71,7 +151,14 @@ impl LineItemBuilder {
         Ok(LineItem {
             account,
             value,
+            commodity: self
+                .commodity
+                .unwrap_or_else(|| DEFAULT_COMMODITY.to_string()),
+            price: self.price,
+            price_is_total: self.price_is_total,
             is_real,
+            elided: false,
+            comment: None,
         })
     }
 }
@@ -83,20 +170,49 @@ impl ToOwned for LineItem {
         Self {
             account: self.account.to_string(),
             value: self.value,
+            commodity: self.commodity.to_string(),
+            price: self.price.clone(),
+            price_is_total: self.price_is_total,
             is_real: self.is_real,
+            elided: self.elided,
+            comment: self.comment.clone(),
         }
     }
 
     fn clone_into(&self, target: &mut Self::Owned) {
         target.account = self.account.to_string();
         target.value = self.value;
+        target.commodity = self.commodity.to_string();
+        target.price = self.price.clone();
+        target.price_is_total = self.price_is_total;
         target.is_real = self.is_real;
+        target.elided = self.elided;
+        target.comment = self.comment.clone();
     }
 }
 
 impl PartialEq for LineItem {
     fn eq(&self, other: &Self) -> bool {
-        self.is_real == other.is_real && self.account == self.account && self.value == other.value
+        self.is_real == other.is_real
+            && self.account == other.account
+            && self.value == other.value
+            && self.commodity == other.commodity
+            && self.price == other.price
+            && self.price_is_total == other.price_is_total
+    }
+}
+
+impl Eq for LineItem {}
+
+/// Hashes exactly the fields `PartialEq` compares, so `elided` and `comment` are left out.
+impl Hash for LineItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.is_real.hash(state);
+        self.account.hash(state);
+        self.value.hash(state);
+        self.commodity.hash(state);
+        self.price.hash(state);
+        self.price_is_total.hash(state);
     }
 }
 
@@ -112,79 +228,223 @@ impl PartialOrd for LineItem {
     }
 }
 
-impl TryFrom<&str> for LineItem {
-    type Error = LineItemBuilderError;
+/// Split a posting into its account, whether it is real, and the text after the account. The
+/// account ends at the first run of two spaces.
+fn split_account(value: &str) -> Result<(String, bool, &str), LineItemBuilderError> {
+    let mut split = value.split("  ");
+    let lhs = split
+        .next()
+        .ok_or(LineItemBuilderError::MissingAccount)?
+        .trim();
+    let rhs = split.last().map(str::trim).unwrap_or_default();
+    let is_real = match (lhs.starts_with('['), lhs.ends_with(']')) {
+        _ if lhs.is_empty() => return Err(LineItemBuilderError::MissingIsReal),
+        (true, true) => false,
+        (false, false) => true,
+        _ => return Err(LineItemBuilderError::MissingIsReal),
+    };
+    let account = if is_real {
+        lhs.to_string()
+    } else {
+        match lhs.get(1..lhs.len() - 1) {
+            Some(s) => s.to_string(),
+            None => return Err(LineItemBuilderError::MissingAccount),
+        }
+    };
+    Ok((account, is_real, rhs))
+}
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut split = value.split("  ");
-        let lhs = split
-            .next()
-            .ok_or(LineItemBuilderError::MissingAccount)?
-            .trim();
-        let rhs = split
-            .last()
-            .ok_or(LineItemBuilderError::MissingValue)?
-            .trim();
-        let is_real = match (lhs.get(0..1), lhs.get(lhs.len() - 1..)) {
-            (Some("["), Some("]")) => false,
-            (_, None) | (None, _) | (Some("["), Some(_)) | (Some(_), Some("]")) => {
-                return Err(LineItemBuilderError::MissingIsReal)
-            }
-            _ => true,
+/// `split_account` for a posting whose account is wrapped in double quotes, as in
+/// `"Assets:Big  Bank"  $1.00` or `["Assets:Big  Bank"]`, so that it may contain two spaces in a
+/// row. `None` if the account is not quoted.
+fn split_quoted_account(value: &str) -> Result<Option<(String, bool, &str)>, LineItemBuilderError> {
+    let value = value.trim_start();
+    let (is_real, quoted) = match value.strip_prefix("[\"") {
+        Some(quoted) => (false, quoted),
+        None => match value.strip_prefix('"') {
+            Some(quoted) => (true, quoted),
+            None => return Ok(None),
+        },
+    };
+    let (account, rest) = quoted
+        .split_once('"')
+        .ok_or(LineItemBuilderError::MissingAccount)?;
+    let rest = if is_real {
+        rest
+    } else {
+        rest.strip_prefix(']')
+            .ok_or(LineItemBuilderError::MissingIsReal)?
+    };
+    if account.trim().is_empty() {
+        return Err(LineItemBuilderError::MissingAccount);
+    }
+    let rhs = rest.split("  ").last().map(str::trim).unwrap_or_default();
+    Ok(Some((account.to_string(), is_real, rhs)))
+}
+
+/// Whether `account` has to be quoted to be read back as one account: it contains two spaces in a
+/// row or a tab.
+pub fn account_needs_quotes(account: &str) -> bool {
+    account.contains("  ") || account.contains('\t')
+}
+
+impl LineItem {
+    /// Parse a posting such as `Assets:Checking  $1.00` or `[Budget:Food]  -$5`. The amount may
+    /// be left off. An account containing two spaces in a row, which would otherwise end it, can
+    /// be written in double quotes: `"Assets:Big  Bank"  $1.00`.
+    pub fn parse_with(value: &str, options: &ParseOptions) -> Result<Self, LineItemBuilderError> {
+        let (account, is_real, rhs) = match split_quoted_account(value)? {
+            Some(split) => split,
+            None => split_account(value)?,
         };
-        let account = if is_real {
-            lhs.to_string()
-        } else {
-            match lhs.get(1..lhs.len() - 1) {
-                Some(s) => s.to_string(),
-                None => return Err(LineItemBuilderError::MissingAccount),
-            }
+        if rhs.is_empty() {
+            return Ok(LineItem {
+                account,
+                value: 0,
+                commodity: DEFAULT_COMMODITY.to_string(),
+                price: None,
+                price_is_total: false,
+                is_real,
+                elided: true,
+                comment: None,
+            });
+        }
+        let (amount, price, price_is_total) = match rhs.split_once("@@") {
+            Some((amount, total)) => (amount, Some(parse_amount(total, options)?), true),
+            None => match rhs.split_once('@') {
+                Some((amount, price)) => (amount, Some(parse_amount(price, options)?), false),
+                None => (rhs, None, false),
+            },
         };
-        let rhs: String = rhs.chars().filter(|c| *c != '$').collect();
-        let value: f64 = rhs.parse().or(Err(LineItemBuilderError::MissingValue))?;
+        let amount = parse_amount(amount, options)?;
         Ok(LineItem {
             account,
-            value: (value * 100.0).round() as i64,
+            value: amount.value,
+            commodity: amount.commodity,
+            price,
+            price_is_total,
             is_real,
+            elided: false,
+            comment: None,
         })
     }
+
+    pub fn amount(&self) -> Amount {
+        Amount::new(self.value, &self.commodity)
+    }
+
+    /// The amount as a plain decimal number with two places, such as `-12.50`, without its
+    /// commodity. Together with `commodity` this lets a custom renderer lay out the two apart.
+    pub fn amount_string(&self) -> String {
+        format!("{:.02}", self.value as f64 / 100.0)
+    }
+
+    pub fn commodity(&self) -> &str {
+        &self.commodity
+    }
+
+    /// The same posting with its amount's sign flipped. The price, being per unit or unsigned,
+    /// is kept.
+    pub fn negated(&self) -> Self {
+        Self {
+            value: -self.value,
+            ..self.to_owned()
+        }
+    }
+
+    /// What the posting is worth for balancing: its amount, its quantity times its price, or its
+    /// total price with the sign of its quantity.
+    pub fn cost(&self) -> Amount {
+        match &self.price {
+            Some(total) if self.price_is_total => {
+                Amount::new(total.value.abs() * self.value.signum(), &total.commodity)
+            }
+            Some(price) => Amount::new(
+                (self.value as f64 * price.value as f64 / 100.0).round() as i64,
+                &price.commodity,
+            ),
+            None => self.amount(),
+        }
+    }
+
+    /// The price of one unit: `price` as written, or a total price divided by the quantity and
+    /// rounded to the nearest hundredth. `None` without a price or for a total over a zero
+    /// quantity.
+    pub fn unit_price(&self) -> Option<Amount> {
+        let price = self.price.as_ref()?;
+        if !self.price_is_total {
+            return Some(price.clone());
+        }
+        if self.value == 0 {
+            return None;
+        }
+        let unit = price.value.abs() as f64 * 100.0 / self.value.abs() as f64;
+        Some(Amount::new(unit.round() as i64, &price.commodity))
+    }
 }
 
+/// Reads the amount in dollars, unlike `TryFrom<String>` which reads it in cents.
+impl TryFrom<&str> for LineItem {
+    type Error = LineItemBuilderError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        LineItem::parse_with(value, &ParseOptions::default())
+    }
+}
+
+/// Same as `TryFrom<&str>`: the amount is read in dollars.
+impl FromStr for LineItem {
+    type Err = LineItemBuilderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LineItem::try_from(s)
+    }
+}
+
+/// Reads the amount in cents, unlike `TryFrom<&str>` which reads it in dollars.
 impl TryFrom<String> for LineItem {
     type Error = LineItemBuilderError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let mut split = value.split("  ");
-        let lhs = split
-            .next()
-            .ok_or(LineItemBuilderError::MissingAccount)?
-            .trim();
-        let rhs = split
-            .last()
-            .ok_or(LineItemBuilderError::MissingValue)?
-            .trim();
-        let is_real = match (lhs.get(0..1), lhs.get(lhs.len() - 1..)) {
-            (Some("["), Some("]")) => false,
-            (_, None) | (None, _) | (Some("["), Some(_)) | (Some(_), Some("]")) => {
-                return Err(LineItemBuilderError::MissingIsReal)
-            }
-            _ => true,
-        };
-        let account = if is_real {
-            lhs.to_string()
-        } else {
-            match lhs.get(1..lhs.len() - 1) {
-                Some(s) => s.to_string(),
-                None => return Err(LineItemBuilderError::MissingAccount),
-            }
-        };
-        let rhs: String = rhs.chars().filter(|c| *c != '$').collect();
-        let value: i64 = rhs.parse().or(Err(LineItemBuilderError::MissingValue))?;
-        Ok(LineItem {
-            account,
-            value,
-            is_real,
-        })
+        LineItem::parse_with(&value, &ParseOptions::new().unit(AmountUnit::Cents))
+    }
+}
+
+/// How to handle bytes that are not valid UTF-8 when parsing raw input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Utf8Decoding {
+    /// Reject input containing invalid UTF-8 with `LineItemBuilderError::InvalidUtf8`
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD
+    Lossy,
+}
+
+impl Utf8Decoding {
+    pub fn decode(self, bytes: &[u8]) -> Result<Cow<'_, str>, LineItemBuilderError> {
+        match self {
+            Self::Strict => std::str::from_utf8(bytes)
+                .map(Cow::Borrowed)
+                .or(Err(LineItemBuilderError::InvalidUtf8)),
+            Self::Lossy => Ok(String::from_utf8_lossy(bytes)),
+        }
+    }
+}
+
+impl LineItem {
+    pub fn try_from_bytes(
+        bytes: &[u8],
+        decoding: Utf8Decoding,
+    ) -> Result<Self, LineItemBuilderError> {
+        LineItem::try_from(decoding.decode(bytes)?.as_ref())
+    }
+}
+
+impl TryFrom<&[u8]> for LineItem {
+    type Error = LineItemBuilderError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        LineItem::try_from_bytes(value, Utf8Decoding::Strict)
     }
 }
 
@@ -197,40 +457,328 @@ impl TryFrom<LineItemBuilder> for LineItem {
 
 impl Display for LineItem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let full_name = if self.is_real {
-            self.account.to_owned()
-        } else {
-            format!("[{}]", self.account)
-        };
-        let value_float = self.value as f64 / 100.0;
-        write!(f, "{}  \t${:.02}", full_name, value_float)
+        write!(f, "{}", self.display_with(&DisplayFormat::default()))
     }
 }
 
 #[derive(Debug)]
 pub struct Transaction {
-    date: chrono::DateTime<Local>,
-    desc: String,
-    line_items: Vec<LineItem>,
+    pub(crate) date: chrono::DateTime<Local>,
+    pub(crate) desc: String,
+    pub(crate) tags: Vec<(String, String)>,
+    /// Comment lines under the header that are not tags, without their `;`
+    pub(crate) comments: Vec<String>,
+    pub(crate) line_items: Vec<LineItem>,
+}
+
+/// Transactions are equal when they fall on the same day, have the same description, and have
+/// the same postings in any order, compared as by `LineItem`'s `PartialEq`. Tags and the time of
+/// day are ignored, as in `content_hash`, so equal transactions have equal hashes.
+impl PartialEq for Transaction {
+    fn eq(&self, other: &Self) -> bool {
+        if self.date.date_naive() != other.date.date_naive()
+            || self.desc != other.desc
+            || self.line_items.len() != other.line_items.len()
+        {
+            return false;
+        }
+        let mut unmatched: Vec<&LineItem> = other.line_items.iter().collect();
+        self.line_items.iter().all(
+            |line| match unmatched.iter().position(|other| *other == line) {
+                Some(i) => {
+                    unmatched.swap_remove(i);
+                    true
+                }
+                None => false,
+            },
+        )
+    }
+}
+
+impl Eq for Transaction {}
+
+/// Consistent with `PartialEq`: the day, the description, and the postings in an order of their
+/// own hashes, so neither tags, the time of day, nor posting order change it. For a hash that is
+/// stable across runs and releases, see `content_hash`.
+impl Hash for Transaction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.date.date_naive().hash(state);
+        self.desc.hash(state);
+        let mut postings: Vec<u64> = self
+            .line_items
+            .iter()
+            .map(|line| {
+                let mut hasher = DefaultHasher::new();
+                line.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        postings.sort_unstable();
+        postings.hash(state);
+    }
+}
+
+/// Whether `key:value` can be written as an hledger tag. Keys may not contain whitespace or `:`,
+/// and neither part may contain `,` or a line break, which would end the tag early.
+pub fn is_valid_tag(key: &str, value: &str) -> bool {
+    !key.is_empty()
+        && !key.contains(|c: char| c.is_whitespace() || c == ':' || c == ',')
+        && !value.contains([',', '\n', '\r'])
+}
+
+/// Split an hledger-style `payee | note` description into its payee and optional note.
+///
+/// A description without a `|` is all payee.
+pub fn split_description(desc: &str) -> (&str, Option<&str>) {
+    match desc.split_once('|') {
+        Some((payee, note)) => (payee.trim(), Some(note.trim())),
+        None => (desc.trim(), None),
+    }
 }
 
 impl Transaction {
-    pub fn post(&self, file: PathBuf) -> Result<(), std::io::Error> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(file)?;
-        writeln!(file, "{}", self)?;
+    /// Assemble a transaction as given, with no tags.
+    ///
+    /// This performs no validation whatsoever: the postings need not balance, elided amounts are
+    /// not inferred, and the description may be empty. It exists for building fixtures, such as
+    /// an unbalanced transaction to exercise `Display`; use `TransactionBuilder::balance` for
+    /// anything meant to be posted.
+    pub fn new_unchecked<S>(
+        date: chrono::DateTime<Local>,
+        desc: S,
+        line_items: Vec<LineItem>,
+    ) -> Self
+    where
+        S: ToString,
+    {
+        Self {
+            date,
+            desc: desc.to_string(),
+            tags: Vec::new(),
+            comments: Vec::new(),
+            line_items,
+        }
+    }
+
+    pub fn date(&self) -> chrono::DateTime<Local> {
+        self.date
+    }
+
+    /// A fingerprint of the date, description, and postings, for spotting the same transaction
+    /// generated twice. Tags are left out and the postings are sorted first, so neither changes
+    /// the result.
+    ///
+    /// Unlike `std::hash::Hash`, the value is fixed: it is 64-bit FNV-1a over a canonical text
+    /// form, so it stays the same across runs, platforms, and releases of this crate, and other
+    /// tools can compute it too. Format it with `{:016x}` for a stable identifier.
+    pub fn content_hash(&self) -> u64 {
+        let mut postings: Vec<String> = self
+            .line_items
+            .iter()
+            .map(|l| {
+                let price = match &l.price {
+                    Some(price) if l.price_is_total => {
+                        format!("total\t{}\t{}", price.value, price.commodity)
+                    }
+                    Some(price) => format!("{}\t{}", price.value, price.commodity),
+                    None => String::new(),
+                };
+                format!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    if l.is_real { "real" } else { "virtual" },
+                    l.account,
+                    l.value,
+                    l.commodity,
+                    price
+                )
+            })
+            .collect();
+        postings.sort();
+        let canonical = format!(
+            "{}\n{}\n{}",
+            self.date.format("%Y-%m-%d"),
+            self.desc,
+            postings.join("\n")
+        );
+        fnv1a(canonical.as_bytes())
+    }
+
+    /// `content_hash` as 16 hex digits, the value of the `id` tag written by `--idempotent`.
+    pub fn id(&self) -> String {
+        format!("{:016x}", self.content_hash())
+    }
+
+    /// Add a `key:value` tag. This does not change `content_hash`.
+    pub fn with_tag<S>(mut self, key: S, value: S) -> Self
+    where
+        S: ToString,
+    {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Add a comment line under the header, after any others. This does not change
+    /// `content_hash`.
+    pub fn with_comment<S>(mut self, comment: S) -> Self
+    where
+        S: ToString,
+    {
+        self.comments.push(comment.to_string());
+        self
+    }
+
+    pub fn postings(&self) -> impl Iterator<Item = &LineItem> {
+        self.line_items.iter()
+    }
+
+    pub fn desc(&self) -> &str {
+        &self.desc
+    }
+
+    /// A builder holding the mirror image of this transaction: every posting negated and the
+    /// description prefixed with `Reversal of`. The date is left for the caller to set.
+    pub fn reversal(&self) -> TransactionBuilder {
+        let builder = TransactionBuilder::new()
+            .desc(format!("Reversal of {}", self.desc))
+            .line_items(self.line_items.iter().map(LineItem::negated).collect());
+        self.comments
+            .iter()
+            .fold(builder, |builder, comment| builder.comment(comment))
+    }
+
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// The transaction without its comment lines or any posting's trailing comment. Tags are
+    /// kept.
+    pub fn without_comments(self) -> Self {
+        Self {
+            comments: Vec::new(),
+            line_items: self
+                .line_items
+                .into_iter()
+                .map(|line| LineItem {
+                    comment: None,
+                    ..line
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    pub fn payee(&self) -> &str {
+        split_description(&self.desc).0
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        split_description(&self.desc).1
+    }
+
+    /// Write the transaction, without a trailing newline, straight to `w`. The header, each tag
+    /// and comment, and each posting go to `w` as they are formatted rather than being collected
+    /// into one `String` first, so wrap `w` in a `BufWriter` when writing many transactions.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.display_with(&DisplayFormat::default()).write_to(w)
+    }
+
+    /// Append the transaction to `file`. Unless `create` is set, a missing file is an error
+    /// rather than silently started afresh, so a mistyped path cannot split a journal.
+    #[cfg(feature = "native")]
+    pub fn post(&self, file: PathBuf, create: bool) -> Result<(), std::io::Error> {
+        let mut file = OpenOptions::new().append(true).create(create).open(file)?;
+        self.write_to(&mut file)?;
+        writeln!(file)?;
         Ok(())
     }
 }
 
+/// Append every transaction to `file` in a single write while holding an exclusive lock on it, so
+/// the journal either gains all of them or none. Build and balance the whole batch before calling
+/// this. `create` is as for `Transaction::post`.
+#[cfg(feature = "native")]
+pub fn post_many(
+    transactions: &[Transaction],
+    file: PathBuf,
+    create: bool,
+) -> Result<(), std::io::Error> {
+    post_many_with(transactions, file, &DisplayFormat::default(), create)
+}
+
+/// `post_many`, laying out each transaction with `format`.
+#[cfg(feature = "native")]
+pub fn post_many_with(
+    transactions: &[Transaction],
+    file: PathBuf,
+    format: &DisplayFormat,
+    create: bool,
+) -> Result<(), std::io::Error> {
+    append_locked(file, render_batch(transactions, format).as_bytes(), create)
+}
+
+/// The transactions as `post_many_with` writes them: each followed by a newline, with a blank
+/// line between them.
+pub fn render_batch(transactions: &[Transaction], format: &DisplayFormat) -> String {
+    transactions
+        .iter()
+        .map(|transaction| format!("{}\n", transaction.display_with(format)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Append `bytes` to `file` in a single write while holding an exclusive lock on it. `create` is
+/// as for `Transaction::post`.
+#[cfg(feature = "native")]
+pub fn append_locked(file: PathBuf, bytes: &[u8], create: bool) -> Result<(), std::io::Error> {
+    let mut file = OpenOptions::new().append(true).create(create).open(file)?;
+    file.lock()?;
+    file.write_all(bytes)?;
+    file.flush()
+}
+
 #[derive(Debug)]
 pub struct TransactionBuilder {
     date: Option<chrono::DateTime<Local>>,
     desc: Option<String>,
+    tags: Vec<(String, String)>,
+    comments: Vec<String>,
     line_items: Vec<LineItem>,
+    expectation: Option<Expectation>,
+    /// See `balance_tolerance`
+    tolerance: i64,
+    /// See `check_virtual_balance`
+    check_virtual: bool,
+}
+
+/// What a template is supposed to produce, checked by `TransactionBuilder::balance` to catch
+/// templates that still balance but post to the wrong place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    /// Exactly this many postings
+    Count(usize),
+    /// Exactly these accounts, in any order, with virtual ones written as `[Account]`
+    Accounts(Vec<String>),
+}
+
+/// A number is read as a posting count, anything else as a comma separated list of accounts.
+impl From<&str> for Expectation {
+    fn from(value: &str) -> Self {
+        match value.trim().parse() {
+            Ok(count) => Self::Count(count),
+            Err(_) => Self::Accounts(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .map(String::from)
+                    .collect(),
+            ),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -238,23 +786,83 @@ pub enum TransactionBuilderError {
     MissingDate,
     MissingDesc,
     NotEnoughLineItems,
-    DoesNotBalance(i64),
+    /// What each unbalanced commodity is off by
+    DoesNotBalance(Vec<Amount>),
+    /// More than one real, or more than one virtual, posting has an elided amount
+    TooManyElided,
+    WrongLineItemCount {
+        expected: usize,
+        found: usize,
+    },
+    /// The postings' accounts differ from `Expectation::Accounts`
+    UnexpectedAccounts {
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    /// `TransactionBuilder::split` was given weights `split_amount` cannot use
+    InvalidSplitWeights,
 }
 
 impl std::fmt::Display for TransactionBuilderError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::MissingDate => write!(f, "transaction is missing a date"),
+            Self::MissingDesc => write!(f, "transaction is missing a description"),
+            Self::NotEnoughLineItems => write!(f, "a transaction needs at least two postings"),
+            Self::DoesNotBalance(residuals) => write!(
+                f,
+                "transaction does not balance by {}",
+                residuals
+                    .iter()
+                    .map(Amount::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::WrongLineItemCount { expected, found } => write!(
+                f,
+                "expected {} postings but the transaction has {}",
+                expected, found
+            ),
+            Self::UnexpectedAccounts {
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "postings do not match the expected accounts; missing: [{}], unexpected: [{}]",
+                missing.join(", "),
+                unexpected.join(", ")
+            ),
+            Self::TooManyElided => write!(
+                f,
+                "only one real and one virtual posting may leave out its amount"
+            ),
+            Self::InvalidSplitWeights => write!(
+                f,
+                "split weights must be non-negative, finite, and not all zero"
+            ),
+        }
     }
 }
 
 impl std::error::Error for TransactionBuilderError {}
 
+impl Default for TransactionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TransactionBuilder {
     pub fn new() -> Self {
         Self {
             date: None,
             desc: None,
+            tags: Vec::new(),
+            comments: Vec::new(),
             line_items: Vec::new(),
+            expectation: None,
+            tolerance: 0,
+            check_virtual: true,
         }
     }
 
@@ -275,6 +883,46 @@ impl TransactionBuilder {
         }
     }
 
+    pub fn payee_note<P, N>(self, payee: P, note: Option<N>) -> Self
+    where
+        P: ToString,
+        N: ToString,
+    {
+        match note {
+            Some(note) => self.desc(format!("{} | {}", payee.to_string(), note.to_string())),
+            None => self.desc(payee),
+        }
+    }
+
+    /// Attach an hledger `key:value` tag, written as a comment under the transaction header.
+    pub fn tag<K, V>(self, key: K, value: V) -> Self
+    where
+        K: ToString,
+        V: ToString,
+    {
+        let mut tags = self.tags;
+        tags.push((key.to_string(), value.to_string()));
+        Self { tags, ..self }
+    }
+
+    /// Add a comment line under the transaction header, after any tags. `comment` is written
+    /// after `; `.
+    pub fn comment<S>(self, comment: S) -> Self
+    where
+        S: ToString,
+    {
+        let mut comments = self.comments;
+        comments.push(comment.to_string());
+        Self { comments, ..self }
+    }
+
+    pub fn expect(self, expectation: Expectation) -> Self {
+        Self {
+            expectation: Some(expectation),
+            ..self
+        }
+    }
+
     pub fn line_items(self, line_items: Vec<LineItem>) -> Self {
         Self { line_items, ..self }
     }
@@ -288,6 +936,67 @@ impl TransactionBuilder {
         }
     }
 
+    /// Add a real posting taking `total` cents of `DEFAULT_COMMODITY` out of `source`, and one
+    /// posting per target receiving its weighted share. The shares come from `split_amount`, so
+    /// they sum to exactly `total` and the added postings balance among themselves.
+    pub fn split<S, T>(
+        self,
+        source: S,
+        targets: &[(T, f64)],
+        total: i64,
+    ) -> Result<Self, TransactionBuilderError>
+    where
+        S: ToString,
+        T: ToString,
+    {
+        let weights: Vec<f64> = targets.iter().map(|(_, weight)| *weight).collect();
+        let parts =
+            split_amount(total, &weights).ok_or(TransactionBuilderError::InvalidSplitWeights)?;
+        let posting = |account: String, value: i64| LineItem {
+            account,
+            value,
+            commodity: DEFAULT_COMMODITY.to_string(),
+            price: None,
+            price_is_total: false,
+            is_real: true,
+            elided: false,
+            comment: None,
+        };
+        let mut builder = self.add_line(posting(source.to_string(), -total));
+        for ((account, _), part) in targets.iter().zip(parts) {
+            builder = builder.add_line(posting(account.to_string(), part));
+        }
+        Ok(builder)
+    }
+
+    /// Accept a transaction whose postings, in every commodity, sum to within `cents` of zero
+    /// instead of exactly zero. The postings are left as they are, so the imbalance stays in the
+    /// output. Defaults to 0. This does not apply when an elided posting takes up the difference.
+    pub fn balance_tolerance(self, cents: i64) -> Self {
+        Self {
+            tolerance: cents.abs(),
+            ..self
+        }
+    }
+
+    /// Whether the virtual postings must sum to zero like the real ones. Defaults to true. When
+    /// false, virtual postings are free-form annotations; an elided virtual posting still takes
+    /// up whatever the others leave over.
+    pub fn check_virtual_balance(self, check_virtual: bool) -> Self {
+        Self {
+            check_virtual,
+            ..self
+        }
+    }
+
+    /// Convert `item`, such as a `&str` or a `LineItemBuilder`, into a posting and add it.
+    pub fn try_add_line<T>(self, item: T) -> Result<Self, T::Error>
+    where
+        T: TryInto<LineItem>,
+    {
+        Ok(self.add_line(item.try_into()?))
+    }
+
     pub fn current_virt_balance(&self) -> i64 {
         self.line_items
             .iter()
@@ -304,35 +1013,171 @@ impl TransactionBuilder {
             .sum()
     }
 
-    pub fn balance(self) -> Result<Transaction, TransactionBuilderError> {
-        let date = self.date.ok_or(TransactionBuilderError::MissingDate)?;
-        let desc = self.desc.ok_or(TransactionBuilderError::MissingDesc)?;
-        if self.line_items.len() < 2 {
-            return Err(TransactionBuilderError::NotEnoughLineItems);
-        }
-
-        let virt_balance: i64 = self
+    /// What the postings of one class (real or virtual) are off by in each commodity that does
+    /// not balance. A single elided posting in the class makes up the difference, as long as
+    /// only one commodity is off.
+    fn residual(&self, is_real: bool) -> Result<Vec<Amount>, TransactionBuilderError> {
+        let elided = self
             .line_items
             .iter()
-            .filter(|l| !l.is_real)
-            .map(|l| l.value)
-            .sum();
-        if virt_balance != 0 {
-            return Err(TransactionBuilderError::DoesNotBalance(virt_balance));
+            .filter(|l| l.is_real == is_real && l.elided)
+            .count();
+        let totals = self.stated_totals(is_real);
+        match elided {
+            0 if !is_real && !self.check_virtual => Ok(Vec::new()),
+            0 if totals.iter().all(|t| t.value.abs() <= self.tolerance) => Ok(Vec::new()),
+            0 => Ok(totals),
+            1 if totals.len() <= 1 => Ok(Vec::new()),
+            1 => Err(TransactionBuilderError::DoesNotBalance(totals)),
+            _ => Err(TransactionBuilderError::TooManyElided),
         }
-        let real_balance: i64 = self
+    }
+
+    /// Non-zero per-commodity sums of the cost of the postings of one class that have an amount.
+    fn stated_totals(&self, is_real: bool) -> Vec<Amount> {
+        let mut totals: BTreeMap<String, i64> = BTreeMap::new();
+        for line in self
             .line_items
             .iter()
-            .filter(|l| l.is_real)
-            .map(|l| l.value)
-            .sum();
-        if real_balance != 0 {
-            return Err(TransactionBuilderError::DoesNotBalance(real_balance));
+            .filter(|l| l.is_real == is_real && !l.elided)
+        {
+            let cost = line.cost();
+            *totals.entry(cost.commodity).or_default() += cost.value;
+        }
+        totals
+            .into_iter()
+            .filter(|(_, value)| *value != 0)
+            .map(|(commodity, value)| Amount::new(value, commodity))
+            .collect()
+    }
+
+    /// The amount an elided posting of one class needs to balance it.
+    fn balancing_amount(&self, is_real: bool) -> Amount {
+        match self.stated_totals(is_real).pop() {
+            Some(total) => Amount::new(-total.value, total.commodity),
+            None => Amount::new(0, DEFAULT_COMMODITY),
+        }
+    }
+
+    /// The elided postings, with the amounts `balance` would fill in for them.
+    pub fn infer_elided(&self) -> Result<Vec<LineItem>, TransactionBuilderError> {
+        let mut inferred = Vec::new();
+        for is_real in [false, true] {
+            self.residual(is_real)?;
+            if let Some(elided) = self
+                .line_items
+                .iter()
+                .find(|l| l.is_real == is_real && l.elided)
+            {
+                let amount = self.balancing_amount(is_real);
+                let mut line = elided.to_owned();
+                line.value = amount.value;
+                line.commodity = amount.commodity;
+                inferred.push(line);
+            }
+        }
+        Ok(inferred)
+    }
+
+    fn check_expectation(&self) -> Result<(), TransactionBuilderError> {
+        match &self.expectation {
+            None => Ok(()),
+            Some(Expectation::Count(expected)) if *expected != self.line_items.len() => {
+                Err(TransactionBuilderError::WrongLineItemCount {
+                    expected: *expected,
+                    found: self.line_items.len(),
+                })
+            }
+            Some(Expectation::Count(_)) => Ok(()),
+            Some(Expectation::Accounts(expected)) => {
+                let mut missing = expected.to_owned();
+                let mut unexpected = Vec::new();
+                for line in &self.line_items {
+                    let name = if line.is_real {
+                        line.account.to_owned()
+                    } else {
+                        format!("[{}]", line.account)
+                    };
+                    match missing.iter().position(|a| *a == name) {
+                        Some(i) => {
+                            missing.remove(i);
+                        }
+                        None => unexpected.push(name),
+                    }
+                }
+                if missing.is_empty() && unexpected.is_empty() {
+                    Ok(())
+                } else {
+                    Err(TransactionBuilderError::UnexpectedAccounts {
+                        missing,
+                        unexpected,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Check the builder without consuming it and report every problem found, rather than only
+    /// the first one like `balance` does. An empty result means `balance` will succeed.
+    pub fn validate(&self) -> Vec<TransactionBuilderError> {
+        let mut errors = Vec::new();
+        if self.date.is_none() {
+            errors.push(TransactionBuilderError::MissingDate);
+        }
+        if self.desc.is_none() {
+            errors.push(TransactionBuilderError::MissingDesc);
+        }
+        if self.line_items.len() < 2 {
+            errors.push(TransactionBuilderError::NotEnoughLineItems);
+        }
+        if let Err(e) = self.check_expectation() {
+            errors.push(e);
+        }
+        for is_real in [false, true] {
+            match self.residual(is_real) {
+                Ok(residual) if residual.is_empty() => (),
+                Ok(residual) => errors.push(TransactionBuilderError::DoesNotBalance(residual)),
+                Err(e) => errors.push(e),
+            }
+        }
+        errors
+    }
+
+    pub fn balance(mut self) -> Result<Transaction, TransactionBuilderError> {
+        let date = self
+            .date
+            .take()
+            .ok_or(TransactionBuilderError::MissingDate)?;
+        let desc = self
+            .desc
+            .take()
+            .ok_or(TransactionBuilderError::MissingDesc)?;
+        if self.line_items.len() < 2 {
+            return Err(TransactionBuilderError::NotEnoughLineItems);
+        }
+        self.check_expectation()?;
+
+        for is_real in [false, true] {
+            let residual = self.residual(is_real)?;
+            if !residual.is_empty() {
+                return Err(TransactionBuilderError::DoesNotBalance(residual));
+            }
+            let amount = self.balancing_amount(is_real);
+            if let Some(elided) = self
+                .line_items
+                .iter_mut()
+                .find(|l| l.is_real == is_real && l.elided)
+            {
+                elided.value = amount.value;
+                elided.commodity = amount.commodity;
+            }
         }
 
         Ok(Transaction {
             date,
             desc,
+            tags: self.tags,
+            comments: self.comments,
             line_items: self.line_items,
         })
     }
@@ -345,15 +1190,254 @@ impl TryFrom<TransactionBuilder> for Transaction {
     }
 }
 
+impl<'a> IntoIterator for &'a Transaction {
+    type Item = &'a LineItem;
+    type IntoIter = std::slice::Iter<'a, LineItem>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.line_items.iter()
+    }
+}
+
 impl Display for Transaction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let date_str = self.date.format("%Y-%m-%d");
-        let lines = self
-            .line_items
-            .iter()
-            .map(|l| format!("    {}", l))
-            .collect::<Vec<String>>()
-            .join("\n");
-        write!(f, "{} {}\n{}", date_str, self.desc, lines)
+        write!(f, "{}", self.display_with(&DisplayFormat::default()))
+    }
+}
+
+/// 64-bit FNV-1a, chosen for `Transaction::content_hash` because it is trivial to reimplement.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Distribute `total` cents across `weights` so the parts always sum exactly to `total`.
+///
+/// Each part is truncated toward zero and whatever cents are left over go to the part with the
+/// largest weight (the first one on ties). Returns `None` if `weights` is empty, contains a
+/// negative or non-finite weight, or sums to zero.
+pub fn split_amount(total: i64, weights: &[f64]) -> Option<Vec<i64>> {
+    if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+        return None;
+    }
+    let weight_sum: f64 = weights.iter().sum();
+    if weight_sum <= 0.0 {
+        return None;
+    }
+
+    let mut parts: Vec<i64> = weights
+        .iter()
+        .map(|w| (total as f64 * w / weight_sum).trunc() as i64)
+        .collect();
+    let remainder = total - parts.iter().sum::<i64>();
+    let largest = weights
+        .iter()
+        .enumerate()
+        .fold(0, |best, (i, w)| if *w > weights[best] { i } else { best });
+    parts[largest] += remainder;
+    Some(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parenthesized_amounts_keep_virtual_brackets() {
+        let posting = LineItem::from_str("[Assets:Budget]  ($12.00)").unwrap();
+        assert_eq!(posting.account, "Assets:Budget");
+        assert!(!posting.is_real);
+        assert_eq!(posting.value, -1200);
+
+        let posting = LineItem::from_str("Assets:Cash  $(12.00)").unwrap();
+        assert!(posting.is_real);
+        assert_eq!(posting.value, -1200);
+    }
+
+    #[test]
+    fn brackets_and_parentheses_do_not_mix() {
+        assert!(LineItem::from_str("[Assets:Budget  ($12.00)").is_err());
+        assert!(LineItem::from_str("[Assets:Budget]  ($12.00]").is_err());
+        assert!(LineItem::from_str("Assets:Cash  [$12.00]").is_err());
+    }
+
+    fn from_lines(lines: &[&str]) -> TransactionBuilder {
+        lines.iter().fold(
+            TransactionBuilder::new().date(Local::now()).desc("test"),
+            |b, line| b.try_add_line(*line).unwrap(),
+        )
+    }
+
+    #[test]
+    fn balances_across_commodities_through_a_total_price() {
+        let builder = from_lines(&[
+            "Assets:Brokerage  3 AAPL @@ $450.00",
+            "Assets:Cash  $-450.00",
+        ]);
+        assert!(builder.validate().is_empty());
+        assert!(builder.balance().is_ok());
+    }
+
+    #[test]
+    fn balances_across_commodities_through_a_unit_price() {
+        let builder = from_lines(&[
+            "Assets:Brokerage  3 AAPL @ $150.00",
+            "Assets:Cash  $-450.00",
+        ]);
+        assert!(builder.validate().is_empty());
+        assert!(builder.balance().is_ok());
+    }
+
+    #[test]
+    fn reports_the_residual_of_each_commodity() {
+        let builder = from_lines(&[
+            "Assets:Brokerage  3 AAPL",
+            "Assets:Cash  $-450.00",
+            "Assets:Euro  10 EUR @ $1.10",
+        ]);
+        match builder.balance() {
+            Err(TransactionBuilderError::DoesNotBalance(residual)) => assert_eq!(
+                residual,
+                vec![Amount::new(-43900, "$"), Amount::new(300, "AAPL")]
+            ),
+            other => panic!("expected DoesNotBalance, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn infers_an_elided_posting_in_the_commodity_left_over() {
+        let builder = from_lines(&["Assets:Brokerage  3 AAPL @ $150.00", "Assets:Cash"]);
+        let inferred = builder.infer_elided().unwrap();
+        assert_eq!(inferred.len(), 1);
+        assert_eq!(inferred[0].amount(), Amount::new(-45000, "$"));
+
+        let transaction = builder.balance().unwrap();
+        let cash = transaction.postings().last().unwrap();
+        assert_eq!(cash.amount(), Amount::new(-45000, "$"));
+    }
+
+    #[test]
+    fn infers_one_elided_posting_per_class() {
+        let builder = from_lines(&[
+            "Expenses:Food  10 EUR",
+            "Assets:Cash",
+            "[Budget:Food]  $-5.00",
+            "[Budget:Available]",
+        ]);
+        let inferred = builder.infer_elided().unwrap();
+        assert_eq!(inferred[0].amount(), Amount::new(500, "$"));
+        assert_eq!(inferred[1].amount(), Amount::new(-1000, "EUR"));
+    }
+
+    #[test]
+    fn an_elided_posting_cannot_cover_two_commodities() {
+        let builder = from_lines(&[
+            "Expenses:Food  10 EUR",
+            "Expenses:Fees  $1.00",
+            "Assets:Cash",
+        ]);
+        assert!(matches!(
+            builder.balance(),
+            Err(TransactionBuilderError::DoesNotBalance(residual)) if residual.len() == 2
+        ));
+        let builder = from_lines(&["Expenses:Food  $1.00", "Assets:Cash", "Assets:Bank"]);
+        assert!(matches!(
+            builder.balance(),
+            Err(TransactionBuilderError::TooManyElided)
+        ));
+    }
+
+    #[test]
+    fn split_amount_gives_leftover_cents_to_the_first_largest_weight() {
+        assert_eq!(split_amount(100, &[1.0, 1.0, 1.0]), Some(vec![34, 33, 33]));
+        assert_eq!(split_amount(100, &[1.0, 2.0]), Some(vec![33, 67]));
+        assert_eq!(
+            split_amount(-100, &[1.0, 1.0, 1.0]),
+            Some(vec![-34, -33, -33])
+        );
+    }
+
+    #[test]
+    fn split_amount_parts_sum_to_the_total() {
+        for total in [1, 99, 100, 12345, -777] {
+            let parts = split_amount(total, &[0.3, 0.3, 0.4, 1.7]).unwrap();
+            assert_eq!(parts.iter().sum::<i64>(), total);
+        }
+    }
+
+    #[test]
+    fn split_amount_rejects_unusable_weights() {
+        assert_eq!(split_amount(100, &[]), None);
+        assert_eq!(split_amount(100, &[1.0, -1.0]), None);
+        assert_eq!(split_amount(100, &[1.0, f64::NAN]), None);
+        assert_eq!(split_amount(100, &[1.0, f64::INFINITY]), None);
+        assert_eq!(split_amount(100, &[0.0, 0.0]), None);
+    }
+
+    #[test]
+    fn split_balances() {
+        let transaction = TransactionBuilder::new()
+            .date(Local::now())
+            .desc("Dinner")
+            .split(
+                "Assets:Cash",
+                &[
+                    ("Expenses:Food", 1.0),
+                    ("Expenses:Drinks", 1.0),
+                    ("Expenses:Tip", 1.0),
+                ],
+                100,
+            )
+            .unwrap()
+            .balance()
+            .unwrap();
+        let values: Vec<i64> = transaction.postings().map(|p| p.value).collect();
+        assert_eq!(values, vec![-100, 34, 33, 33]);
+        assert!(matches!(
+            TransactionBuilder::new().split("Assets:Cash", &[("Expenses:Food", 0.0)], 100),
+            Err(TransactionBuilderError::InvalidSplitWeights)
+        ));
+    }
+
+    fn hash_of<T: Hash>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn equal_transactions_hash_alike() {
+        let lines = [
+            "Expenses:Food  $4.50",
+            "[Budget:Food]  $-4.50",
+            "Assets:Cash  $-4.50",
+            "[Budget:Available]  $4.50",
+        ];
+        let a = from_lines(&lines).tag("id", "a").balance().unwrap();
+        let reordered = [lines[2], lines[3], lines[0], lines[1]];
+        let b = from_lines(&reordered).comment("noted").balance().unwrap();
+        assert_eq!(a, b);
+        assert_eq!(hash_of(&a), hash_of(&b));
+    }
+
+    #[test]
+    fn differing_transactions_are_unequal() {
+        let a = from_lines(&["Expenses:Food  $4.50", "Assets:Cash  $-4.50"])
+            .balance()
+            .unwrap();
+        let other_amount = from_lines(&["Expenses:Food  $4.51", "Assets:Cash  $-4.51"])
+            .balance()
+            .unwrap();
+        let other_class = from_lines(&["[Expenses:Food]  $4.50", "[Assets:Cash]  $-4.50"])
+            .balance()
+            .unwrap();
+        let other_desc = from_lines(&["Expenses:Food  $4.50", "Assets:Cash  $-4.50"])
+            .desc("other")
+            .balance()
+            .unwrap();
+        for other in [&other_amount, &other_class, &other_desc] {
+            assert_ne!(&a, other);
+        }
     }
 }